@@ -1,62 +1,722 @@
+use crate::radiswap::radiswap::{Radiswap, RadiswapFunctions};
 use scrypto::prelude::*;
 
+/// Optional sale settings. Caps and purchase limits are denominated in the unit of `price`: the pay
+/// token, or USD for oracle-priced sales. Payments in `extra_pay_tokens` count at the value of the
+/// bought tokens in that unit.
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug, Default)]
+pub struct LaunchpadConfig {
+    /// Minimum raise; below it at `end_time` the sale fails and buyers are refunded.
+    pub soft_cap: Option<Decimal>,
+    /// Maximum raise; purchases that would go past it are rejected.
+    pub hard_cap: Option<Decimal>,
+    pub min_purchase: Option<Decimal>,
+    pub max_purchase: Option<Decimal>,
+    /// Maximum spend per buyer badge. Requires `buyer_badge`.
+    pub max_per_buyer: Option<Decimal>,
+    /// When set, every purchase must present a proof of one non-fungible of this resource.
+    pub buyer_badge: Option<ResourceAddress>,
+    /// Caps each buyer's spend by how many platform tokens they hold or stake. Requires `buyer_badge`.
+    pub tiers: Option<TierConfig>,
+    /// Requires every purchase to present a KYC credential issued by a third party.
+    pub kyc: Option<KycConfig>,
+    /// Restricted round between `start_time` and the public phase.
+    pub whitelist: Option<WhitelistPhase>,
+    /// Without a schedule purchases are fully claimable at `end_time`.
+    pub vesting: Option<VestingSchedule>,
+    /// Sells at a descending price instead of the fixed `price`, which becomes the floor.
+    pub dutch_auction: Option<DutchAuction>,
+    /// Further accepted pay resources and the price of one sold token in each. Fixed-price sales only.
+    pub extra_pay_tokens: IndexMap<ResourceAddress, Decimal>,
+    /// Prices the sale in USD and converts to the pay token at the oracle rate on every purchase.
+    pub oracle: Option<OracleConfig>,
+    /// Latest `end_time` the owner may extend the sale to. Without it the sale cannot be extended.
+    pub max_end_time: Option<i64>,
+    /// Accepts commitments beyond the tokens for sale. After `end_time` every purchase is filled pro
+    /// rata and the excess payment is refunded on the first claim.
+    pub overflow: bool,
+    /// How purchase NFTs are shown in wallets.
+    pub receipt: ReceiptDisplay,
+    /// Purchase NFTs cannot be withdrawn from the account they are first deposited to, and are
+    /// claimed from there with `claim_bound`.
+    pub soulbound: bool,
+    /// Rewards referrers with a share of the purchases they bring in.
+    pub referral: Option<ReferralConfig>,
+    /// Cut of the proceeds taken by the platform before the revenue split.
+    pub platform_fee: Option<PlatformFee>,
+    /// Shares of the proceeds after the platform fee. The owner keeps what is left.
+    pub revenue_split: Vec<RevenueShare>,
+    /// Pairs part of the proceeds with sold tokens in a new pool once the sale has succeeded.
+    pub liquidity: Option<LiquidityConfig>,
+}
+
+/// Wallet display settings for the purchase NFT resource and its non-fungibles.
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct ReceiptDisplay {
+    pub name: String,
+    pub description: String,
+    pub icon_url: Option<UncheckedUrl>,
+    pub info_url: Option<UncheckedUrl>,
+    /// Image of every purchase NFT. Falls back to `icon_url`.
+    pub key_image_url: Option<UncheckedUrl>,
+}
+
+impl Default for ReceiptDisplay {
+    fn default() -> Self {
+        Self {
+            name: "Launchpad Purchase NFT".to_string(),
+            description: "Receipt for tokens bought in a launchpad sale, redeemable once the sale has ended"
+                .to_string(),
+            icon_url: None,
+            info_url: None,
+            key_image_url: None,
+        }
+    }
+}
+
+/// Buyers are eligible if their buyer badge is on the owner-managed allowlist or, when `badge` is
+/// set, is a non-fungible of that resource. The same proof is the buyer badge of a sale that has
+/// one, so `badge` and the allowlist must then be of the `buyer_badge` resource.
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct WhitelistPhase {
+    /// Start of the public phase.
+    pub end_time: i64,
+    pub price: Decimal,
+    /// Sold tokens available during the whitelist phase.
+    pub allocation: Decimal,
+    pub badge: Option<ResourceAddress>,
+}
+
+/// Buyers present a proof of their platform-token holding with every purchase and may spend up to
+/// `base_allocation` times the multiplier of the highest tier whose threshold they reach.
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct TierConfig {
+    pub token: TierToken,
+    /// Allocation at a multiplier of 1, in the unit of `price`.
+    pub base_allocation: Decimal,
+    /// Ordered by ascending threshold.
+    pub tiers: Vec<Tier>,
+}
+
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub enum TierToken {
+    /// Held amount of a fungible platform token.
+    Fungible(ResourceAddress),
+    /// Staking-receipt NFTs; the staked amount is the Decimal at `amount_field` in their data.
+    StakingReceipt {
+        resource: ResourceAddress,
+        amount_field: u8,
+    },
+}
+
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct Tier {
+    pub threshold: Decimal,
+    pub multiplier: Decimal,
+}
+
+/// Credentials are non-fungibles of `credential` whose data holds the holder's jurisdiction as a
+/// String and the expiry of the credential in seconds since the Unix epoch as an i64.
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct KycConfig {
+    pub credential: ResourceAddress,
+    pub jurisdiction_field: u8,
+    pub expiry_field: u8,
+    /// Jurisdictions blocked at launch. The owner can change the list while the sale runs.
+    pub blocked_jurisdictions: Vec<String>,
+}
+
+/// Release schedule for purchased tokens, counted from `end_time`.
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct VestingSchedule {
+    /// Fraction of each purchase unlocked at `end_time`, between 0 and 1.
+    pub tge_unlock: Decimal,
+    /// Seconds after `end_time` before linear vesting starts.
+    pub cliff: i64,
+    /// Seconds over which the remainder vests once the cliff has passed.
+    pub duration: i64,
+    /// When set, vesting advances in steps of this many seconds.
+    pub step: Option<i64>,
+}
+
+/// The price falls from `start_price` at `start_time` to the launchpad `price` at `end_time`.
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct DutchAuction {
+    pub start_price: Decimal,
+    /// When set, the price drops every this many seconds instead of continuously.
+    pub step: Option<i64>,
+    /// Every buyer pays the clearing price and the difference is refunded on the first claim. The
+    /// clearing price is the price at which the sale sold out, or the floor if it never did.
+    pub uniform_clearing: bool,
+}
+
+/// `price` and the whitelist price are in USD when an oracle is configured.
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct OracleConfig {
+    /// Component with a `get_price(resource) -> (Decimal, i64)` method returning the USD price of
+    /// the resource and the unix time in seconds it was last updated.
+    pub component: ComponentAddress,
+    /// Maximum age in seconds of the oracle price.
+    pub max_staleness: i64,
+    /// Largest relative change from the rate used by the previous purchase, e.g. 0.1 for 10%. The
+    /// owner re-anchors it at the current rate with `reset_oracle_rate` after a larger move.
+    pub max_deviation: Decimal,
+}
+
+/// Referrers are identified by a non-fungible of `badge` and claim their rewards with a proof of
+/// it once the sale has succeeded. Not supported for sales that refund overpayments.
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct ReferralConfig {
+    pub badge: ResourceAddress,
+    /// Share of every referred payment credited to the referrer, e.g. 0.05 for 5%.
+    pub reward: Decimal,
+    /// Maximum total reward per referrer, in the unit of `price`.
+    pub cap: Decimal,
+}
+
+/// Basis points are hundredths of a percent of the proceeds.
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct PlatformFee {
+    pub bps: u16,
+    /// Badge presented to `withdraw_revenue_share` to collect the fee.
+    pub recipient: NonFungibleGlobalId,
+}
+
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct RevenueShare {
+    /// Badge presented to `withdraw_revenue_share` to collect the share.
+    pub recipient: NonFungibleGlobalId,
+    pub bps: u16,
+}
+
+/// Every purchase reserves `share` of its tokens on top of the tokens bought, and the same share of
+/// its payment is held back from the proceeds, so the pool opens at the average sale price. Only
+/// supported for sales in a single pay token that do not refund overpayments.
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub struct LiquidityConfig {
+    /// Between 0 and 1.
+    pub share: Decimal,
+    pub pool: LiquidityPool,
+    /// Seconds after `bootstrap_liquidity` before the owner can withdraw the pool units.
+    pub lock_duration: i64,
+}
+
+#[derive(ScryptoSbor, ManifestSbor, Clone, Debug)]
+pub enum LiquidityPool {
+    /// A new `Radiswap` pool charging `fee` on swaps, between 0 and 1.
+    Radiswap { fee: Decimal },
+    TwoResourcePool,
+}
+
+#[derive(ScryptoSbor, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SalePhase {
+    Upcoming,
+    Active,
+    Ended,
+    Failed,
+    Cancelled,
+}
+
+/// Everything a sale page shows, in one call. Prices, caps, `total_raised` and `total_committed` are
+/// in the unit of the sale price: the pay token, or USD for oracle-priced sales.
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct SaleInfo {
+    pub phase: SalePhase,
+    pub paused: bool,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub sold_token: ResourceAddress,
+    pub pay_token: ResourceAddress,
+    /// Price of one sold token right now, including whitelist and auction pricing.
+    pub current_price: Decimal,
+    /// Fixed price, or the floor of a Dutch auction.
+    pub base_price: Decimal,
+    pub clearing_price: Option<Decimal>,
+    pub extra_pay_tokens: IndexMap<ResourceAddress, Decimal>,
+    pub soft_cap: Option<Decimal>,
+    pub hard_cap: Option<Decimal>,
+    /// Net of refunds, as in `total_raised`.
+    pub total_raised: Decimal,
+    pub total_committed: Decimal,
+    pub tokens_for_sale: Decimal,
+    /// Tokens bought so far. Commitments beyond the tokens for sale count in an overflow sale.
+    pub tokens_sold: Decimal,
+    pub tokens_remaining: Decimal,
+    pub total_claimed: Decimal,
+    pub purchases: u64,
+    /// Distinct buyer badges that have bought, as in `unique_buyers`. `None` without a buyer badge.
+    pub participants: Option<u64>,
+    pub vesting: Option<VestingSchedule>,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone, Debug)]
+pub struct LaunchpadCreatedEvent {
+    pub launchpad: ComponentAddress,
+    pub sold_token: ResourceAddress,
+    pub pay_token: ResourceAddress,
+    pub tokens_for_sale: Decimal,
+    pub price: Decimal,
+    pub start_time: i64,
+    pub end_time: i64,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone, Debug)]
+pub struct PurchaseEvent {
+    /// Local id of the purchase NFT minted for this purchase.
+    pub purchase_id: NonFungibleLocalId,
+    pub buyer: Option<NonFungibleGlobalId>,
+    pub pay_token: ResourceAddress,
+    pub pay_amount: Decimal,
+    pub token_amount: Decimal,
+    pub price: Decimal,
+    pub referrer: Option<NonFungibleGlobalId>,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone, Debug)]
+pub struct ClaimEvent {
+    pub purchase_id: NonFungibleLocalId,
+    pub token_amount: Decimal,
+    /// Pay tokens returned, from a failed sale or a settlement refund.
+    pub refund_amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone, Debug)]
+pub struct FundsWithdrawnEvent {
+    pub pay_token: ResourceAddress,
+    pub amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone, Debug)]
+pub struct UnsoldWithdrawnEvent {
+    pub amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone, Debug)]
+pub struct RevenueShareWithdrawnEvent {
+    pub recipient: NonFungibleGlobalId,
+    pub pay_token: ResourceAddress,
+    pub amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone, Debug)]
+pub struct LiquidityBootstrappedEvent {
+    pub pool: ComponentAddress,
+    pub pay_amount: Decimal,
+    pub token_amount: Decimal,
+    pub pool_units: Decimal,
+    pub unlock_time: i64,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone, Debug)]
+pub struct ReferralRewardsClaimedEvent {
+    pub referrer: NonFungibleGlobalId,
+    pub pay_token: ResourceAddress,
+    pub amount: Decimal,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone, Debug)]
+pub struct SalePausedEvent {
+    pub paused: bool,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone, Debug)]
+pub struct SaleCancelledEvent {
+    /// Whether the sale was cancelled after `end_time` through `emergency_refund`.
+    pub emergency: bool,
+}
+
+#[derive(ScryptoSbor, ScryptoEvent, Clone, Debug)]
+pub struct ScheduleChangedEvent {
+    pub start_time: i64,
+    pub end_time: i64,
+}
+
 #[derive(ScryptoSbor, NonFungibleData)]
 struct PurchaseData {
+    /// Tokens owed. In an overflow sale this is what the commitment asked for until the purchase is
+    /// settled on its first claim.
+    #[mutable]
     amount: Decimal,
+    pay_token: ResourceAddress,
+    #[mutable]
+    paid_amount: Decimal,
+    #[mutable]
+    claimed_so_far: Decimal,
+    #[mutable]
+    settled: bool,
+    /// Average price of one sold token in `pay_token`.
+    #[mutable]
+    price_paid: Decimal,
+    /// Seconds since the Unix epoch of the purchase that minted the NFT.
+    purchased_at: i64,
+    launchpad: ComponentAddress,
+    name: String,
+    key_image_url: UncheckedUrl,
 }
 
-#[blueprint]
-mod launchpad {
+/// Rewards credited to one referrer.
+#[derive(ScryptoSbor, Clone, Default)]
+struct ReferralRewards {
+    /// Total reward in the unit of `price`, checked against the referral cap.
+    value: Decimal,
+    /// Unclaimed reward per pay token.
+    owed: IndexMap<ResourceAddress, Decimal>,
+}
 
-    const SIMPLE_BADGE: ResourceManager = resource_manager!(
-        "resource_sim1t4kwg8fa7ldhwh8exe5w4acjhp9v982svmxp3yqa8ncruad4pf6m22"
-    );
+/// A purchase taken by `buy` or `buy_more`, before it is recorded on a purchase NFT.
+struct Purchase {
+    buyer: Option<NonFungibleGlobalId>,
+    pay_token: ResourceAddress,
+    amount: Decimal,
+    paid_amount: Decimal,
+    price: Decimal,
+    referrer: Option<NonFungibleGlobalId>,
+}
 
-    enable_function_auth! {
-        instantiate_launchpad => rule!(require(SIMPLE_BADGE.address()));
+impl Purchase {
+    fn into_event(self, purchase_id: NonFungibleLocalId) -> PurchaseEvent {
+        PurchaseEvent {
+            purchase_id,
+            buyer: self.buyer,
+            pay_token: self.pay_token,
+            pay_amount: self.paid_amount,
+            token_amount: self.amount,
+            price: self.price,
+            referrer: self.referrer,
+        }
     }
+}
+
+#[blueprint]
+#[events(
+    LaunchpadCreatedEvent,
+    PurchaseEvent,
+    ClaimEvent,
+    FundsWithdrawnEvent,
+    RevenueShareWithdrawnEvent,
+    UnsoldWithdrawnEvent,
+    LiquidityBootstrappedEvent,
+    ReferralRewardsClaimedEvent,
+    SalePausedEvent,
+    SaleCancelledEvent,
+    ScheduleChangedEvent
+)]
+mod launchpad {
 
     enable_method_auth! {
         methods {
             buy => PUBLIC;
+            buy_more => PUBLIC;
             claim => PUBLIC;
+            claim_bound => PUBLIC;
+            claim_referral_rewards => PUBLIC;
             withdraw_funds => restrict_to: [OWNER];
+            withdraw_revenue_share => PUBLIC;
             withdraw_unsold_tokens => restrict_to: [OWNER];
+            bootstrap_liquidity => PUBLIC;
+            withdraw_pool_units => restrict_to: [OWNER];
+            add_to_allowlist => restrict_to: [OWNER];
+            remove_from_allowlist => restrict_to: [OWNER];
+            block_jurisdictions => restrict_to: [OWNER];
+            unblock_jurisdictions => restrict_to: [OWNER];
+            pause => restrict_to: [OWNER];
+            unpause => restrict_to: [OWNER];
+            cancel_sale => restrict_to: [OWNER];
+            emergency_refund => restrict_to: [OWNER];
+            reschedule => restrict_to: [OWNER];
+            extend_sale => restrict_to: [OWNER];
+            end_sale_early => restrict_to: [OWNER];
+            reset_oracle_rate => restrict_to: [OWNER];
+            is_paused => PUBLIC;
+            is_cancelled => PUBLIC;
+            is_allowlisted => PUBLIC;
+            is_jurisdiction_blocked => PUBLIC;
+            in_whitelist_phase => PUBLIC;
+            current_price => PUBLIC;
+            clearing_price => PUBLIC;
+            outstanding_refunds => PUBLIC;
+            fill_ratio => PUBLIC;
+            tokens_for_sale => PUBLIC;
+            claimable_amount => PUBLIC;
+            allocation_for => PUBLIC;
+            whitelist_sold => PUBLIC;
+            phase => PUBLIC;
+            get_sale_info => PUBLIC;
+            sale_failed => PUBLIC;
+            config => PUBLIC;
+            soft_cap => PUBLIC;
+            total_raised => PUBLIC;
+            total_committed => PUBLIC;
+            total_tokens_sold => PUBLIC;
+            total_claimed => PUBLIC;
+            purchase_count => PUBLIC;
+            unique_buyers => PUBLIC;
+            raised_in => PUBLIC;
+            price_in => PUBLIC;
+            purchased_by => PUBLIC;
+            referral_rewards => PUBLIC;
+            revenue_share => PUBLIC;
+            reserved_liquidity => PUBLIC;
+            liquidity_pool => PUBLIC;
+            liquidity_unlock_time => PUBLIC;
             sold_token_vault_amount => PUBLIC;
+            committed_tokens => PUBLIC;
+            available_tokens => PUBLIC;
             pay_token_vault_amount => PUBLIC;
+            pay_vault_amount => PUBLIC;
             start_time => PUBLIC;
             end_time => PUBLIC;
             current_time => PUBLIC;
         }
     }
 
-    struct Launchpad {
+    pub struct Launchpad {
         start_time: i64,
         end_time: i64,
         sold_token: ResourceAddress,
         pay_token: ResourceAddress,
         price: Decimal,
         sold_token_vault: Vault,
-        pay_token_vault: Vault,
+        pay_vaults: IndexMap<ResourceAddress, Vault>,
+        committed_tokens: Decimal,
+        config: LaunchpadConfig,
+        total_committed: Decimal,
+        raised_by_token: IndexMap<ResourceAddress, Decimal>,
+        buyer_purchases: KeyValueStore<NonFungibleGlobalId, Decimal>,
+        referral_rewards: KeyValueStore<NonFungibleGlobalId, ReferralRewards>,
+        /// Unclaimed referral rewards per pay token, held back from the proceeds.
+        referral_owed: IndexMap<ResourceAddress, Decimal>,
+        /// Set once a referrer has been paid, after which the sale can no longer be unwound.
+        referral_rewards_paid: bool,
+        allowlist: KeyValueStore<NonFungibleGlobalId, ()>,
+        blocked_jurisdictions: KeyValueStore<String, ()>,
+        whitelist_sold: Decimal,
+        tokens_for_sale: Decimal,
+        tokens_sold: Decimal,
+        total_claimed: Decimal,
+        purchase_count: u64,
+        /// Buyer badges of the configured resource that have bought, each counted once in
+        /// `unique_buyers`.
+        buyers: KeyValueStore<NonFungibleGlobalId, ()>,
+        unique_buyers: u64,
+        sold_out_price: Option<Decimal>,
+        /// Purchase NFTs of a sale that refunds overpayments which have not been settled yet, with
+        /// what they paid and the tokens they asked for.
+        unsettled_purchases: u64,
+        unsettled_paid: Decimal,
+        unsettled_tokens: Decimal,
+        /// Pay tokens held back from the proceeds for refunds not yet claimed when they were
+        /// distributed. What is left once every purchase is settled goes to the owner.
+        refund_reserve: Decimal,
+        /// Oracle rate of the last purchase or owner reset, which the next rate is checked against.
+        reference_oracle_rate: Option<Decimal>,
+        /// Set once the proceeds have been divided between the owner and the revenue recipients.
+        funds_withdrawn: bool,
+        owner_proceeds: IndexMap<ResourceAddress, Decimal>,
+        revenue_shares: KeyValueStore<NonFungibleGlobalId, IndexMap<ResourceAddress, Decimal>>,
+        /// Sold tokens and pay tokens held back for the liquidity pool until it is created.
+        liquidity_tokens: Decimal,
+        liquidity_funds: Decimal,
+        liquidity_pool: Option<ComponentAddress>,
+        pool_units: Option<Vault>,
+        liquidity_unlock_time: Option<i64>,
+        paused: bool,
+        cancelled: bool,
         purchase_nft: ResourceManager,
         launchpad_manager: FungibleVault,
     }
 
     impl Launchpad {
+        /// `owner_rule` guards the owner methods (withdrawals, allowlist management) of the new sale.
+        /// Only a `LaunchpadFactory` of this package may create sales, so that its registry lists
+        /// every one of them.
         pub fn instantiate_launchpad(
+            owner_rule: AccessRule,
             start_time: i64,
             end_time: i64,
             sold_token: ResourceAddress,
             pay_token: ResourceAddress,
             price: Decimal,
             sold_token_bucket: Bucket,
+            config: LaunchpadConfig,
         ) -> Global<Launchpad> {
+            Runtime::assert_access_rule(rule!(require(global_caller(BlueprintId::new(
+                &Runtime::package_address(),
+                "LaunchpadFactory",
+            )))));
             assert!(start_time < end_time, "End time must be after start time");
             assert!(price > Decimal::zero(), "Price must be greater than zero");
             assert!(!sold_token_bucket.is_empty(), "Must provide tokens to sell");
+            if let Some(soft_cap) = config.soft_cap {
+                assert!(soft_cap > Decimal::zero(), "Soft cap must be greater than zero");
+            }
+            if let Some(hard_cap) = config.hard_cap {
+                assert!(
+                    hard_cap >= config.soft_cap.unwrap_or(Decimal::zero()),
+                    "Hard cap must not be below the soft cap"
+                );
+            }
+            if let (Some(min_purchase), Some(max_purchase)) = (config.min_purchase, config.max_purchase) {
+                assert!(min_purchase <= max_purchase, "Minimum purchase must not exceed maximum purchase");
+            }
+            assert!(
+                config.max_per_buyer.is_none() || config.buyer_badge.is_some(),
+                "A per-buyer limit requires a buyer badge"
+            );
+            if let Some(tiers) = &config.tiers {
+                assert!(config.buyer_badge.is_some(), "Tiers require a buyer badge");
+                assert!(
+                    tiers.base_allocation > Decimal::zero(),
+                    "Base allocation must be greater than zero"
+                );
+                assert!(!tiers.tiers.is_empty(), "At least one tier is required");
+                assert!(
+                    tiers.tiers.windows(2).all(|pair| pair[0].threshold < pair[1].threshold),
+                    "Tier thresholds must be ascending"
+                );
+                assert!(
+                    tiers.tiers.iter().all(|tier| tier.multiplier > Decimal::zero()),
+                    "Tier multipliers must be greater than zero"
+                );
+            }
+            if let Some(kyc) = &config.kyc {
+                assert!(
+                    kyc.jurisdiction_field != kyc.expiry_field,
+                    "KYC jurisdiction and expiry must be different fields"
+                );
+            }
+            if let Some(max_end_time) = config.max_end_time {
+                assert!(max_end_time >= end_time, "Maximum end time must not be before end time");
+            }
+            if let Some(whitelist) = &config.whitelist {
+                assert!(
+                    start_time < whitelist.end_time && whitelist.end_time < end_time,
+                    "Whitelist phase must end between start time and end time"
+                );
+                assert!(whitelist.price > Decimal::zero(), "Whitelist price must be greater than zero");
+                assert!(
+                    whitelist.allocation > Decimal::zero()
+                        && whitelist.allocation <= sold_token_bucket.amount(),
+                    "Whitelist allocation must be positive and covered by the tokens for sale"
+                );
+                if let (Some(badge), Some(buyer_badge)) = (whitelist.badge, config.buyer_badge) {
+                    assert!(badge == buyer_badge, "Whitelist badge must be the buyer badge");
+                }
+            }
+            if let Some(vesting) = &config.vesting {
+                assert!(
+                    vesting.tge_unlock >= Decimal::zero() && vesting.tge_unlock <= Decimal::one(),
+                    "TGE unlock must be between 0 and 1"
+                );
+                assert!(
+                    vesting.cliff >= 0 && vesting.duration >= 0,
+                    "Vesting cliff and duration must not be negative"
+                );
+                if let Some(step) = vesting.step {
+                    assert!(step > 0, "Vesting step must be greater than zero");
+                }
+            }
+            if let Some(auction) = &config.dutch_auction {
+                assert!(
+                    auction.start_price > price,
+                    "Auction start price must be above the floor price"
+                );
+                if let Some(step) = auction.step {
+                    assert!(step > 0, "Auction step must be greater than zero");
+                }
+                assert!(
+                    config.whitelist.is_none(),
+                    "A Dutch auction cannot have a whitelist phase"
+                );
+            }
+            if !config.extra_pay_tokens.is_empty() {
+                assert!(
+                    config.whitelist.is_none() && config.dutch_auction.is_none() && !config.overflow,
+                    "Extra pay tokens are only supported for fixed-price sales"
+                );
+            }
+            for (extra_pay_token, extra_price) in &config.extra_pay_tokens {
+                assert!(*extra_pay_token != pay_token, "Extra pay tokens must differ from the pay token");
+                assert!(*extra_price > Decimal::zero(), "Price must be greater than zero");
+            }
+            if let Some(oracle) = &config.oracle {
+                assert!(
+                    config.dutch_auction.is_none() && !config.overflow && config.extra_pay_tokens.is_empty(),
+                    "Oracle pricing is only supported for fixed-price sales in a single pay token"
+                );
+                assert!(oracle.max_staleness >= 0, "Oracle staleness limit must not be negative");
+                assert!(
+                    oracle.max_deviation > Decimal::zero(),
+                    "Oracle deviation limit must be greater than zero"
+                );
+            }
+            if let Some(referral) = &config.referral {
+                assert!(
+                    !config.overflow
+                        && !config.dutch_auction.as_ref().is_some_and(|auction| auction.uniform_clearing),
+                    "Referral rewards are not supported for sales that refund overpayments"
+                );
+                assert!(
+                    referral.reward > Decimal::zero() && referral.reward < Decimal::one(),
+                    "Referral reward must be between 0 and 1"
+                );
+                assert!(referral.cap > Decimal::zero(), "Referral cap must be greater than zero");
+            }
+            if let Some(platform_fee) = &config.platform_fee {
+                assert!(platform_fee.bps <= 10_000, "Platform fee must not exceed 10000 bps");
+            }
+            assert!(
+                config.revenue_split.iter().all(|share| share.bps > 0),
+                "Revenue shares must be greater than zero"
+            );
+            assert!(
+                config.revenue_split.iter().map(|share| share.bps as u32).sum::<u32>() <= 10_000,
+                "Revenue shares must not exceed 10000 bps in total"
+            );
+            if let Some(liquidity) = &config.liquidity {
+                assert!(
+                    !config.overflow
+                        && !config.dutch_auction.as_ref().is_some_and(|auction| auction.uniform_clearing)
+                        && config.extra_pay_tokens.is_empty(),
+                    "Liquidity bootstrapping requires a single pay token and no overpayment refunds"
+                );
+                assert!(
+                    liquidity.share > Decimal::zero() && liquidity.share < Decimal::one(),
+                    "Liquidity share must be between 0 and 1"
+                );
+                let referral_reward = config
+                    .referral
+                    .as_ref()
+                    .map_or(Decimal::zero(), |referral| referral.reward);
+                assert!(
+                    liquidity.share + referral_reward < Decimal::one(),
+                    "Liquidity share and referral reward must leave proceeds for the owner"
+                );
+                assert!(liquidity.lock_duration >= 0, "Liquidity lock duration must not be negative");
+                if let LiquidityPool::Radiswap { fee } = liquidity.pool {
+                    assert!(
+                        fee >= Decimal::zero() && fee <= Decimal::one(),
+                        "Radiswap fee must be between 0 and 1"
+                    );
+                }
+            }
+            if config.overflow {
+                assert!(
+                    config.whitelist.is_none() && config.dutch_auction.is_none(),
+                    "An overflow sale cannot have a whitelist phase or a Dutch auction"
+                );
+            }
 
-            // let owner_role = OwnerRole::Updatable(rule!(require(SIMPLE_BADGE.address())));
-            let owner_role = OwnerRole::Updatable(rule!(require(SIMPLE_BADGE.address())));
+            let tokens_for_sale = sold_token_bucket.amount();
+            let pay_vaults = std::iter::once(pay_token)
+                .chain(config.extra_pay_tokens.keys().copied())
+                .map(|resource| (resource, Vault::new(resource)))
+                .collect();
+            let owner_role = OwnerRole::Updatable(owner_rule);
+            let blocked_jurisdictions = KeyValueStore::new();
+            for jurisdiction in config.kyc.iter().flat_map(|kyc| &kyc.blocked_jurisdictions) {
+                blocked_jurisdictions.insert(jurisdiction.clone(), ());
+            }
 
             // Create launchpad manager badge
             let launchpad_manager_badge = ResourceBuilder::new_fungible(owner_role.clone())
@@ -69,12 +729,22 @@ mod launchpad {
                 })
                 .mint_initial_supply(1);
 
+            let receipt = &config.receipt;
+            let mut purchase_nft_metadata = MetadataInit::new();
+            purchase_nft_metadata.set_and_lock_metadata("name", receipt.name.clone());
+            purchase_nft_metadata.set_and_lock_metadata("symbol", "LPN");
+            purchase_nft_metadata.set_and_lock_metadata("description", receipt.description.clone());
+            if let Some(icon_url) = &receipt.icon_url {
+                purchase_nft_metadata.set_and_lock_metadata("icon_url", icon_url.clone());
+            }
+            if let Some(info_url) = &receipt.info_url {
+                purchase_nft_metadata.set_and_lock_metadata("info_url", info_url.clone());
+            }
+
             let purchase_nft = ResourceBuilder::new_ruid_non_fungible::<PurchaseData>(owner_role.clone())
-                .metadata(metadata! {
-                    init {
-                        "name" => "Launchpad Purchase NFT", locked;
-                        "symbol" => "LPN", locked;
-                    }
+                .metadata(ModuleConfig {
+                    init: purchase_nft_metadata,
+                    roles: RoleAssignmentInit::default(),
                 })
                 .mint_roles(mint_roles!(
                     minter => rule!(require(launchpad_manager_badge.resource_address()));
@@ -84,93 +754,766 @@ mod launchpad {
                     burner => rule!(require(launchpad_manager_badge.resource_address()));
                     burner_updater => rule!(deny_all);
                 ))
+                .non_fungible_data_update_roles(non_fungible_data_update_roles!(
+                    non_fungible_data_updater => rule!(require(launchpad_manager_badge.resource_address()));
+                    non_fungible_data_updater_updater => rule!(deny_all);
+                ))
+                .withdraw_roles(if config.soulbound {
+                    withdraw_roles!(
+                        withdrawer => rule!(deny_all);
+                        withdrawer_updater => rule!(deny_all);
+                    )
+                } else {
+                    None
+                })
                 .create_with_no_initial_supply();
 
-            Self {
+            let launchpad = Self {
                 start_time,
                 end_time,
                 sold_token,
                 pay_token,
                 price,
                 sold_token_vault: Vault::with_bucket(sold_token_bucket),
-                pay_token_vault: Vault::new(pay_token),
+                pay_vaults,
+                committed_tokens: Decimal::zero(),
+                config,
+                total_committed: Decimal::zero(),
+                raised_by_token: IndexMap::new(),
+                buyer_purchases: KeyValueStore::new(),
+                referral_rewards: KeyValueStore::new(),
+                referral_owed: IndexMap::new(),
+                referral_rewards_paid: false,
+                allowlist: KeyValueStore::new(),
+                blocked_jurisdictions,
+                whitelist_sold: Decimal::zero(),
+                tokens_for_sale,
+                tokens_sold: Decimal::zero(),
+                total_claimed: Decimal::zero(),
+                purchase_count: 0,
+                buyers: KeyValueStore::new(),
+                unique_buyers: 0,
+                sold_out_price: None,
+                unsettled_purchases: 0,
+                unsettled_paid: Decimal::zero(),
+                unsettled_tokens: Decimal::zero(),
+                refund_reserve: Decimal::zero(),
+                reference_oracle_rate: None,
+                funds_withdrawn: false,
+                owner_proceeds: IndexMap::new(),
+                revenue_shares: KeyValueStore::new(),
+                liquidity_tokens: Decimal::zero(),
+                liquidity_funds: Decimal::zero(),
+                liquidity_pool: None,
+                pool_units: None,
+                liquidity_unlock_time: None,
+                paused: false,
+                cancelled: false,
                 purchase_nft,
                 launchpad_manager: FungibleVault::with_bucket(launchpad_manager_badge),
             }
             .instantiate()
             .prepare_to_globalize(owner_role)
-            .globalize()
-        }
+            .globalize();
 
-        pub fn buy(&mut self, mut payment: Bucket) -> (NonFungibleBucket, Bucket) {
-            assert!(
-                Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch >= self.start_time,
-                "Sale has not started yet"
-            );
-            assert!(
-                Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch < self.end_time,
-                "Sale has already ended"
-            );
-            assert!(
-                payment.resource_address() == self.pay_token,
-                "Invalid token for purchase"
-            );
+            Runtime::emit_event(LaunchpadCreatedEvent {
+                launchpad: launchpad.address(),
+                sold_token,
+                pay_token,
+                tokens_for_sale,
+                price,
+                start_time,
+                end_time,
+            });
 
-            let amount = payment.amount() / self.price;
-            assert!(
-                amount <= self.sold_token_vault.amount(),
-                "Not enough tokens available for sale"
-            );
+            launchpad
+        }
 
-            self.pay_token_vault.put(payment.take(amount * self.price));
+        pub fn buy(
+            &mut self,
+            mut payment: Bucket,
+            buyer_badge: Option<NonFungibleProof>,
+            tier_proof: Option<Proof>,
+            kyc_credential: Option<NonFungibleProof>,
+            referrer: Option<NonFungibleGlobalId>,
+        ) -> (NonFungibleBucket, Bucket) {
+            let purchase =
+                self.purchase(&mut payment, buyer_badge, tier_proof, kyc_credential, referrer);
 
             let purchase_nft = self.launchpad_manager.authorize_with_amount(1, || {
-                self.purchase_nft.mint_ruid_non_fungible(PurchaseData { amount })
+                self.purchase_nft.mint_ruid_non_fungible(PurchaseData {
+                    amount: purchase.amount,
+                    pay_token: purchase.pay_token,
+                    paid_amount: purchase.paid_amount,
+                    claimed_so_far: Decimal::zero(),
+                    settled: false,
+                    price_paid: purchase.price,
+                    purchased_at: Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch,
+                    launchpad: Runtime::global_address(),
+                    name: self.config.receipt.name.clone(),
+                    key_image_url: self
+                        .config
+                        .receipt
+                        .key_image_url
+                        .clone()
+                        .or_else(|| self.config.receipt.icon_url.clone())
+                        .unwrap_or_else(|| UncheckedUrl::of("")),
+                })
             }).as_non_fungible();
+            if self.refunds_overpayments() {
+                self.unsettled_purchases += 1;
+            }
+
+            Runtime::emit_event(purchase.into_event(purchase_nft.non_fungible_local_id()));
 
             (purchase_nft, payment)
         }
 
-        pub fn claim(&mut self, purchase_nft: NonFungibleBucket) -> Bucket {
+        /// Adds a purchase to an existing purchase NFT instead of minting a new one. The payment must
+        /// be in the currency the NFT was bought with.
+        pub fn buy_more(
+            &mut self,
+            purchase_nft: NonFungibleProof,
+            mut payment: Bucket,
+            buyer_badge: Option<NonFungibleProof>,
+            tier_proof: Option<Proof>,
+            kyc_credential: Option<NonFungibleProof>,
+            referrer: Option<NonFungibleGlobalId>,
+        ) -> Bucket {
+            let purchase_nft =
+                purchase_nft.check_with_message(self.purchase_nft.address(), "Invalid purchase NFT");
+            let purchase_id = purchase_nft.non_fungible_local_id();
+            let purchase_data: PurchaseData = self.purchase_nft.get_non_fungible_data(&purchase_id);
             assert!(
-                Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch >= self.end_time,
-                "Sale has not ended yet"
+                payment.resource_address() == purchase_data.pay_token,
+                "Payment must be in the currency of the purchase NFT"
             );
+
+            let purchase =
+                self.purchase(&mut payment, buyer_badge, tier_proof, kyc_credential, referrer);
+
+            let amount = purchase_data.amount + purchase.amount;
+            let paid_amount = purchase_data.paid_amount + purchase.paid_amount;
+            self.launchpad_manager.authorize_with_amount(1, || {
+                self.purchase_nft.update_non_fungible_data(&purchase_id, "amount", amount);
+                self.purchase_nft.update_non_fungible_data(&purchase_id, "paid_amount", paid_amount);
+                self.purchase_nft.update_non_fungible_data(&purchase_id, "price_paid", paid_amount / amount);
+            });
+
+            Runtime::emit_event(purchase.into_event(purchase_id));
+
+            payment
+        }
+
+        /// Claims any number of purchase NFTs at once. Returns the vested tokens, then a refund
+        /// bucket per pay token owed, then the purchase NFTs that are still vesting.
+        pub fn claim(&mut self, mut purchase_nfts: NonFungibleBucket) -> Vec<Bucket> {
             assert!(
-                purchase_nft.resource_address() == self.purchase_nft.address(),
+                purchase_nfts.resource_address() == self.purchase_nft.address(),
                 "Invalid purchase NFT"
             );
 
-            let purchase_data: PurchaseData = purchase_nft.non_fungible().data();
-            purchase_nft.burn();
+            let (mut buckets, vesting_ids) =
+                self.claim_purchases(purchase_nfts.non_fungibles::<PurchaseData>());
 
-            self.sold_token_vault.take(purchase_data.amount)
+            let still_vesting = purchase_nfts.take_non_fungibles(&vesting_ids);
+            if purchase_nfts.is_empty() {
+                purchase_nfts.drop_empty();
+            } else {
+                self.launchpad_manager.authorize_with_amount(1, || purchase_nfts.burn());
+            }
+
+            if still_vesting.is_empty() {
+                still_vesting.drop_empty();
+            } else {
+                buckets.push(still_vesting.into());
+            }
+            buckets
+        }
+
+        /// Claims soulbound purchase NFTs in place, leaving them in the buyer's account once used up.
+        /// Returns the vested tokens, then a refund bucket per pay token owed.
+        pub fn claim_bound(&mut self, purchase_nfts: NonFungibleProof) -> Vec<Bucket> {
+            // Only soulbound NFTs are marked as used up instead of being burned
+            assert!(self.config.soulbound, "Purchase NFTs are not soulbound, claim them with claim");
+            let purchase_nfts =
+                purchase_nfts.check_with_message(self.purchase_nft.address(), "Invalid purchase NFT");
+            let (buckets, _) = self.claim_purchases(purchase_nfts.non_fungibles::<PurchaseData>());
+            buckets
         }
 
-        pub fn withdraw_funds(&mut self) -> Bucket {
+        /// Returns the owner's proceeds in every accepted currency, main pay token first. The platform
+        /// fee and revenue shares stay behind for their recipients. Once every purchase is settled it
+        /// also sweeps what is left of the refund reserve.
+        pub fn withdraw_funds(&mut self) -> Vec<Bucket> {
+            self.distribute_proceeds();
+            // The reserve rounds every refund up, so some of it is left over once all are paid
+            if self.unsettled_purchases == 0 && self.refund_reserve > Decimal::zero() {
+                *self.owner_proceeds.entry(self.pay_token).or_default() += self.refund_reserve;
+                self.refund_reserve = Decimal::zero();
+            }
+
+            let mut buckets = vec![];
+            for (pay_token, vault) in self.pay_vaults.iter_mut() {
+                let proceeds = self.owner_proceeds.insert(*pay_token, Decimal::zero()).unwrap_or_default();
+                Runtime::emit_event(FundsWithdrawnEvent {
+                    pay_token: *pay_token,
+                    amount: proceeds,
+                });
+                buckets.push(vault.take(proceeds));
+            }
+            buckets
+        }
+
+        /// Pays out the platform fee or revenue share of the presented recipient badge, one bucket
+        /// per pay token.
+        pub fn withdraw_revenue_share(&mut self, recipient_badge: NonFungibleProof) -> Vec<Bucket> {
+            // The badge is matched against the configured recipients below
+            let recipient_badge = recipient_badge.skip_checking();
+            let recipient = NonFungibleGlobalId::new(
+                recipient_badge.resource_address(),
+                recipient_badge.non_fungible_local_id(),
+            );
+            self.distribute_proceeds();
+
+            let owed = std::mem::take(
+                &mut *self
+                    .revenue_shares
+                    .get_mut(&recipient)
+                    .expect("Badge is not a revenue recipient"),
+            );
+            let mut buckets = vec![];
+            for (pay_token, amount) in owed {
+                Runtime::emit_event(RevenueShareWithdrawnEvent {
+                    recipient: recipient.clone(),
+                    pay_token,
+                    amount,
+                });
+                buckets.push(self.pay_vault(pay_token).take(amount));
+            }
+            buckets
+        }
+
+        /// Pays out the referral rewards of the presented referrer badge, one bucket per pay token.
+        /// Rewards are only paid once the sale has ended successfully.
+        pub fn claim_referral_rewards(&mut self, referrer_badge: NonFungibleProof) -> Vec<Bucket> {
+            let referral = self.config.referral.as_ref().expect("Sale has no referral rewards");
+            let referrer_badge =
+                referrer_badge.check_with_message(referral.badge, "Invalid referrer badge");
+            let referrer =
+                NonFungibleGlobalId::new(referral.badge, referrer_badge.non_fungible_local_id());
             assert!(
                 Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch >= self.end_time,
                 "Sale has not ended yet"
             );
+            assert!(!self.refunding(), "Sale is refunding its buyers");
 
-            self.pay_token_vault.take_all()
+            let owed = match self.referral_rewards.get_mut(&referrer) {
+                Some(mut rewards) => std::mem::take(&mut rewards.owed),
+                None => IndexMap::new(),
+            };
+            let mut buckets = vec![];
+            for (pay_token, amount) in owed {
+                *self.referral_owed.get_mut(&pay_token).unwrap() -= amount;
+                self.referral_rewards_paid = true;
+                Runtime::emit_event(ReferralRewardsClaimedEvent {
+                    referrer: referrer.clone(),
+                    pay_token,
+                    amount,
+                });
+                buckets.push(self.pay_vault(pay_token).take(amount));
+            }
+            buckets
         }
 
         pub fn withdraw_unsold_tokens(&mut self) -> Bucket {
             assert!(
-                Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch >= self.end_time,
+                self.cancelled
+                    || Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch >= self.end_time,
                 "Sale has not ended yet"
             );
 
-            self.sold_token_vault.take_all()
+            // Tokens owed to buyers stay in the vault until they are claimed
+            let amount = if self.refunding() {
+                self.sold_token_vault.amount()
+            } else {
+                self.available_tokens()
+            };
+            Runtime::emit_event(UnsoldWithdrawnEvent { amount });
+            self.sold_token_vault.take(amount)
+        }
+
+        /// Adds the reserved proceeds and sold tokens to a new pool once the sale has succeeded. The
+        /// pool units stay locked in the launchpad for the configured period.
+        pub fn bootstrap_liquidity(&mut self) -> ComponentAddress {
+            let liquidity = self
+                .config
+                .liquidity
+                .clone()
+                .expect("Sale has no liquidity bootstrapping");
+            assert!(self.current_time() >= self.end_time, "Sale has not ended yet");
+            assert!(!self.refunding(), "Sale is refunding its buyers");
+            assert!(self.liquidity_pool.is_none(), "Liquidity has already been added");
+            assert!(self.liquidity_tokens > Decimal::zero(), "No tokens were sold");
+
+            let token_amount = self.liquidity_tokens;
+            let pay_amount = self.liquidity_funds;
+            let tokens = self.sold_token_vault.take(token_amount);
+            let funds = self.pay_vault(self.pay_token).take(pay_amount);
+            self.committed_tokens -= token_amount;
+            self.liquidity_tokens = Decimal::zero();
+            self.liquidity_funds = Decimal::zero();
+
+            let (pool, pool_units) = match liquidity.pool {
+                LiquidityPool::Radiswap { fee } => {
+                    let (pool, pool_units) = Blueprint::<Radiswap>::instantiate_radiswap(
+                        tokens.as_fungible(),
+                        funds.as_fungible(),
+                        fee,
+                    );
+                    (pool.address(), pool_units.into())
+                }
+                LiquidityPool::TwoResourcePool => {
+                    let mut pool = Blueprint::<TwoResourcePool>::instantiate(
+                        OwnerRole::None,
+                        rule!(require(global_caller(Runtime::global_address()))),
+                        (self.sold_token, self.pay_token),
+                        None,
+                    );
+                    let (pool_units, change) = pool.contribute((tokens, funds));
+                    // A new pool takes both buckets in full
+                    assert!(change.is_none(), "Pool returned part of the contribution");
+                    (pool.address(), pool_units)
+                }
+            };
+
+            let unlock_time = self.current_time() + liquidity.lock_duration;
+            Runtime::emit_event(LiquidityBootstrappedEvent {
+                pool,
+                pay_amount,
+                token_amount,
+                pool_units: pool_units.amount(),
+                unlock_time,
+            });
+            self.liquidity_pool = Some(pool);
+            self.liquidity_unlock_time = Some(unlock_time);
+            self.pool_units = Some(Vault::with_bucket(pool_units));
+            pool
+        }
+
+        pub fn withdraw_pool_units(&mut self) -> Bucket {
+            let unlock_time = self.liquidity_unlock_time.expect("No liquidity has been added");
+            assert!(self.current_time() >= unlock_time, "Pool units are still locked");
+            self.pool_units.as_mut().unwrap().take_all()
+        }
+
+        pub fn pause(&mut self) {
+            assert!(!self.paused, "Sale is already paused");
+            self.paused = true;
+            Runtime::emit_event(SalePausedEvent { paused: true });
+        }
+
+        pub fn unpause(&mut self) {
+            assert!(self.paused, "Sale is not paused");
+            self.paused = false;
+            Runtime::emit_event(SalePausedEvent { paused: false });
+        }
+
+        /// Stops the sale before `end_time` and lets every buyer redeem their purchase NFT for the pay
+        /// tokens they spent.
+        pub fn cancel_sale(&mut self) {
+            assert!(self.current_time() < self.end_time, "Sale has already ended");
+            assert!(!self.cancelled, "Sale was already cancelled");
+            self.cancelled = true;
+            Runtime::emit_event(SaleCancelledEvent { emergency: false });
+        }
+
+        /// Unwinds a sale that has already ended, as long as the raised funds are still here. Buyers
+        /// are refunded for the tokens they have not claimed yet.
+        pub fn emergency_refund(&mut self) {
+            assert!(self.current_time() >= self.end_time, "Sale has not ended yet, cancel it instead");
+            assert!(!self.cancelled, "Sale was already cancelled");
+            assert!(!self.funds_withdrawn, "Funds have already been withdrawn");
+            assert!(self.liquidity_pool.is_none(), "Liquidity has already been added");
+            // Buyers are refunded in full, which the pay vault no longer covers once referrers are paid
+            assert!(!self.referral_rewards_paid, "Referral rewards have already been paid");
+            self.cancelled = true;
+            Runtime::emit_event(SaleCancelledEvent { emergency: true });
+        }
+
+        /// Moves a sale that has not started yet to a new window.
+        pub fn reschedule(&mut self, start_time: i64, end_time: i64) {
+            let now = self.current_time();
+            assert!(now < self.start_time, "Sale has already started");
+            assert!(now <= start_time, "Start time must not be in the past");
+            assert!(start_time < end_time, "End time must be after start time");
+            if let Some(max_end_time) = self.config.max_end_time {
+                assert!(end_time <= max_end_time, "End time is past the maximum end time");
+            }
+            if let Some(whitelist) = &self.config.whitelist {
+                assert!(
+                    start_time < whitelist.end_time && whitelist.end_time < end_time,
+                    "Whitelist phase must end between start time and end time"
+                );
+            }
+
+            self.start_time = start_time;
+            self.end_time = end_time;
+            Runtime::emit_event(ScheduleChangedEvent { start_time, end_time });
+        }
+
+        /// Pushes back the end of a running sale, up to the configured maximum end time.
+        pub fn extend_sale(&mut self, end_time: i64) {
+            let now = self.current_time();
+            assert!(now >= self.start_time, "Sale has not started yet, reschedule it instead");
+            assert!(now < self.end_time, "Sale has already ended");
+            assert!(!self.cancelled, "Sale was cancelled");
+            assert!(end_time > self.end_time, "New end time must be after the current end time");
+            let max_end_time = self.config.max_end_time.expect("Sale cannot be extended");
+            assert!(end_time <= max_end_time, "End time is past the maximum end time");
+            // Stretching the auction would raise its current price
+            assert!(self.config.dutch_auction.is_none(), "A Dutch auction cannot be extended");
+
+            self.end_time = end_time;
+            Runtime::emit_event(ScheduleChangedEvent {
+                start_time: self.start_time,
+                end_time,
+            });
+        }
+
+        /// Closes a running sale now that the hard cap has been reached.
+        pub fn end_sale_early(&mut self) {
+            let now = self.current_time();
+            assert!(now >= self.start_time, "Sale has not started yet");
+            assert!(now < self.end_time, "Sale has already ended");
+            assert!(!self.cancelled, "Sale was cancelled");
+            let hard_cap = self.config.hard_cap.expect("Sale has no hard cap");
+            assert!(self.total_committed >= hard_cap, "Hard cap has not been reached");
+            // A clearing auction clears at the price it stopped at, not at its floor
+            match &self.config.dutch_auction {
+                Some(auction) if auction.uniform_clearing && self.sold_out_price.is_none() => {
+                    self.sold_out_price = Some(self.auction_price(auction));
+                }
+                _ => {}
+            }
+
+            self.end_time = now;
+            Runtime::emit_event(ScheduleChangedEvent {
+                start_time: self.start_time,
+                end_time: now,
+            });
+        }
+
+        /// Takes the current oracle rate as the reference the next purchases may deviate from, once
+        /// a legitimate move past `max_deviation` has stopped purchases.
+        pub fn reset_oracle_rate(&mut self) {
+            assert!(self.config.oracle.is_some(), "Sale is not oracle-priced");
+            self.reference_oracle_rate = None;
+            self.reference_oracle_rate = self.oracle_rate();
+        }
+
+        pub fn is_paused(&self) -> bool {
+            self.paused
+        }
+
+        pub fn is_cancelled(&self) -> bool {
+            self.cancelled
+        }
+
+        pub fn add_to_allowlist(&mut self, buyers: Vec<NonFungibleGlobalId>) {
+            for buyer in buyers {
+                if let Some(buyer_badge) = self.config.buyer_badge {
+                    assert!(
+                        buyer.resource_address() == buyer_badge,
+                        "Allowlisted buyers must be buyer badges"
+                    );
+                }
+                self.allowlist.insert(buyer, ());
+            }
+        }
+
+        pub fn remove_from_allowlist(&mut self, buyers: Vec<NonFungibleGlobalId>) {
+            for buyer in buyers {
+                self.allowlist.remove(&buyer);
+            }
+        }
+
+        pub fn is_allowlisted(&self, buyer: NonFungibleGlobalId) -> bool {
+            self.allowlist.get(&buyer).is_some()
+        }
+
+        pub fn block_jurisdictions(&mut self, jurisdictions: Vec<String>) {
+            for jurisdiction in jurisdictions {
+                self.blocked_jurisdictions.insert(jurisdiction, ());
+            }
+        }
+
+        pub fn unblock_jurisdictions(&mut self, jurisdictions: Vec<String>) {
+            for jurisdiction in jurisdictions {
+                self.blocked_jurisdictions.remove(&jurisdiction);
+            }
+        }
+
+        pub fn is_jurisdiction_blocked(&self, jurisdiction: String) -> bool {
+            self.blocked_jurisdictions.get(&jurisdiction).is_some()
+        }
+
+        pub fn in_whitelist_phase(&self) -> bool {
+            match &self.config.whitelist {
+                Some(whitelist) => self.current_time() < whitelist.end_time,
+                None => false,
+            }
+        }
+
+        /// Price of one sold token in the pay token, converted at the oracle rate for USD sales.
+        pub fn current_price(&self) -> Decimal {
+            match self.oracle_rate() {
+                Some(rate) => self.quoted_price() / rate,
+                None => self.quoted_price(),
+            }
+        }
+
+        /// The uniform price of a clearing auction, known once it sells out or ends.
+        pub fn clearing_price(&self) -> Option<Decimal> {
+            match &self.config.dutch_auction {
+                Some(auction) if auction.uniform_clearing => match self.sold_out_price {
+                    Some(price) => Some(price),
+                    None if self.current_time() >= self.end_time => Some(self.price),
+                    None => None,
+                },
+                _ => None,
+            }
+        }
+
+        /// Pay tokens owed back to buyers of a clearing auction or an overflow sale that have not
+        /// been claimed yet. Covers every refund even though each claim rounds its own refund up.
+        pub fn outstanding_refunds(&self) -> Decimal {
+            let refunds = if self.config.overflow && self.current_time() >= self.end_time {
+                self.unsettled_paid - self.unsettled_paid * self.fill_ratio()
+            } else if let Some(clearing_price) = self.clearing_price() {
+                self.unsettled_paid - self.unsettled_tokens * clearing_price
+            } else {
+                return Decimal::zero();
+            };
+
+            // Rounding a refund up adds less than two of the pay token's smallest units to it
+            let divisibility = Self::divisibility(self.pay_token);
+            let smallest_unit = Decimal::one() / Decimal::from(10u64.pow(divisibility as u32));
+            refunds.checked_round(divisibility, RoundingMode::ToPositiveInfinity).unwrap()
+                + smallest_unit * 2 * self.unsettled_purchases
+        }
+
+        /// Share of each overflow commitment that is filled, 1 while the sale is not oversubscribed.
+        pub fn fill_ratio(&self) -> Decimal {
+            if !self.config.overflow || self.tokens_sold <= self.tokens_for_sale {
+                return Decimal::one();
+            }
+            self.tokens_for_sale / self.tokens_sold
+        }
+
+        pub fn tokens_for_sale(&self) -> Decimal {
+            self.tokens_for_sale
+        }
+
+        pub fn whitelist_sold(&self) -> Decimal {
+            self.whitelist_sold
+        }
+
+        pub fn claimable_amount(&self, purchase_id: NonFungibleLocalId) -> Decimal {
+            if self.current_time() < self.end_time || self.refunding() {
+                return Decimal::zero();
+            }
+            let purchase_data: PurchaseData = self.purchase_nft.get_non_fungible_data(&purchase_id);
+            let (amount, _) = self.settlement(&purchase_data);
+            self.vested_amount(amount) - purchase_data.claimed_so_far
+        }
+
+        /// Spend cap for a buyer holding `held` platform tokens, zero below the lowest tier. `None`
+        /// when the sale has no tiers.
+        pub fn allocation_for(&self, held: Decimal) -> Option<Decimal> {
+            let tiers = self.config.tiers.as_ref()?;
+            let multiplier = tiers
+                .tiers
+                .iter()
+                .rev()
+                .find(|tier| held >= tier.threshold)
+                .map(|tier| tier.multiplier)
+                .unwrap_or(Decimal::zero());
+            Some(tiers.base_allocation * multiplier)
+        }
+
+        pub fn phase(&self) -> SalePhase {
+            let now = self.current_time();
+            if self.cancelled {
+                SalePhase::Cancelled
+            } else if now < self.start_time {
+                SalePhase::Upcoming
+            } else if now < self.end_time {
+                SalePhase::Active
+            } else if self.sale_failed() {
+                SalePhase::Failed
+            } else {
+                SalePhase::Ended
+            }
+        }
+
+        pub fn get_sale_info(&self) -> SaleInfo {
+            SaleInfo {
+                phase: self.phase(),
+                paused: self.paused,
+                start_time: self.start_time,
+                end_time: self.end_time,
+                sold_token: self.sold_token,
+                pay_token: self.pay_token,
+                current_price: self.quoted_price(),
+                base_price: self.price,
+                clearing_price: self.clearing_price(),
+                extra_pay_tokens: self.config.extra_pay_tokens.clone(),
+                soft_cap: self.config.soft_cap,
+                hard_cap: self.config.hard_cap,
+                total_raised: self.total_raised(),
+                total_committed: self.total_committed,
+                tokens_for_sale: self.tokens_for_sale,
+                tokens_sold: self.tokens_sold,
+                tokens_remaining: self.available_tokens(),
+                total_claimed: self.total_claimed,
+                purchases: self.purchase_count,
+                participants: self.unique_buyers(),
+                vesting: self.config.vesting.clone(),
+            }
+        }
+
+        pub fn sale_failed(&self) -> bool {
+            match self.config.soft_cap {
+                Some(soft_cap) => self.current_time() >= self.end_time && self.total_raised() < soft_cap,
+                None => false,
+            }
+        }
+
+        pub fn config(&self) -> LaunchpadConfig {
+            self.config.clone()
+        }
+
+        pub fn soft_cap(&self) -> Option<Decimal> {
+            self.config.soft_cap
+        }
+
+        /// Total raised across all currencies, valued in the unit of `price`: the pay token, or USD for
+        /// oracle-priced sales. Unaffected by withdrawals. What an overflow sale or clearing auction
+        /// refunds is left out: the former keeps its fill and the latter the clearing price for every
+        /// token sold, though until that price is known every bid counts in full.
+        pub fn total_raised(&self) -> Decimal {
+            if self.config.overflow {
+                return self.total_committed * self.fill_ratio();
+            }
+            match self.clearing_price() {
+                Some(clearing_price) => self.tokens_sold * clearing_price,
+                None => self.total_committed,
+            }
+        }
+
+        /// Value of every purchase as paid, before overpayments are refunded. Caps are checked
+        /// against it.
+        pub fn total_committed(&self) -> Decimal {
+            self.total_committed
+        }
+
+        /// Tokens bought, up to the tokens for sale in an oversubscribed overflow sale.
+        pub fn total_tokens_sold(&self) -> Decimal {
+            if self.config.overflow {
+                return self.tokens_sold.min(self.tokens_for_sale);
+            }
+            self.tokens_sold
+        }
+
+        pub fn total_claimed(&self) -> Decimal {
+            self.total_claimed
+        }
+
+        /// Purchases through `buy` and `buy_more`.
+        pub fn purchase_count(&self) -> u64 {
+            self.purchase_count
+        }
+
+        /// Distinct buyer badges that have bought. `None` without a buyer badge, as purchases can't be
+        /// told apart by buyer then.
+        pub fn unique_buyers(&self) -> Option<u64> {
+            self.config.buyer_badge.map(|_| self.unique_buyers)
+        }
+
+        pub fn raised_in(&self, pay_token: ResourceAddress) -> Decimal {
+            self.raised_by_token
+                .get(&pay_token)
+                .copied()
+                .unwrap_or(Decimal::zero())
+        }
+
+        /// Price of one sold token in `pay_token`, if it is accepted.
+        pub fn price_in(&self, pay_token: ResourceAddress) -> Option<Decimal> {
+            if pay_token == self.pay_token {
+                return Some(self.current_price());
+            }
+            self.config.extra_pay_tokens.get(&pay_token).copied()
+        }
+
+        /// Unclaimed referral rewards of a referrer per pay token.
+        pub fn referral_rewards(&self, referrer: NonFungibleGlobalId) -> IndexMap<ResourceAddress, Decimal> {
+            self.referral_rewards
+                .get(&referrer)
+                .map(|rewards| rewards.owed.clone())
+                .unwrap_or_default()
+        }
+
+        /// Revenue a recipient has yet to withdraw per pay token. Empty until the proceeds are divided
+        /// on the first withdrawal.
+        pub fn revenue_share(&self, recipient: NonFungibleGlobalId) -> IndexMap<ResourceAddress, Decimal> {
+            self.revenue_shares
+                .get(&recipient)
+                .map(|owed| owed.clone())
+                .unwrap_or_default()
+        }
+
+        /// Sold tokens and pay tokens held back for the liquidity pool, until it is created.
+        pub fn reserved_liquidity(&self) -> (Decimal, Decimal) {
+            (self.liquidity_tokens, self.liquidity_funds)
+        }
+
+        pub fn liquidity_pool(&self) -> Option<ComponentAddress> {
+            self.liquidity_pool
+        }
+
+        pub fn liquidity_unlock_time(&self) -> Option<i64> {
+            self.liquidity_unlock_time
+        }
+
+        pub fn purchased_by(&self, buyer: NonFungibleGlobalId) -> Decimal {
+            self.buyer_purchases
+                .get(&buyer)
+                .map(|spent| *spent)
+                .unwrap_or(Decimal::zero())
         }
 
         pub fn sold_token_vault_amount(&self) -> Decimal {
             self.sold_token_vault.amount()
         }
 
+        pub fn committed_tokens(&self) -> Decimal {
+            self.committed_tokens
+        }
+
+        pub fn available_tokens(&self) -> Decimal {
+            self.sold_token_vault.amount() - self.committed_tokens
+        }
+
         pub fn pay_token_vault_amount(&self) -> Decimal {
-            self.pay_token_vault.amount()
+            self.pay_vault_amount(self.pay_token)
+        }
+
+        pub fn pay_vault_amount(&self, pay_token: ResourceAddress) -> Decimal {
+            self.pay_vaults
+                .get(&pay_token)
+                .map(|vault| vault.amount())
+                .unwrap_or(Decimal::zero())
         }
 
         pub fn start_time(&self) -> i64 {
@@ -184,5 +1527,599 @@ mod launchpad {
         pub fn current_time(&self) -> i64 {
             Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch
         }
+
+        /// Takes the payment for a purchase after checking it against the sale settings. The change
+        /// is left in `payment`.
+        fn purchase(
+            &mut self,
+            payment: &mut Bucket,
+            buyer_badge: Option<NonFungibleProof>,
+            tier_proof: Option<Proof>,
+            kyc_credential: Option<NonFungibleProof>,
+            referrer: Option<NonFungibleGlobalId>,
+        ) -> Purchase {
+            assert!(
+                Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch >= self.start_time,
+                "Sale has not started yet"
+            );
+            assert!(
+                Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch < self.end_time,
+                "Sale has already ended"
+            );
+            assert!(!self.cancelled, "Sale was cancelled");
+            assert!(!self.paused, "Sale is paused");
+            self.check_kyc(kyc_credential);
+
+            // The proof is genuine; which resources are acceptable depends on the sale settings
+            let buyer = buyer_badge.map(|badge| {
+                let badge = badge.skip_checking();
+                NonFungibleGlobalId::new(badge.resource_address(), badge.non_fungible_local_id())
+            });
+
+            let pay_token = payment.resource_address();
+            let price = if pay_token != self.pay_token {
+                self.price_in(pay_token).expect("Invalid token for purchase")
+            } else if let Some(rate) = self.oracle_rate() {
+                self.reference_oracle_rate = Some(rate);
+                self.quoted_price() / rate
+            } else {
+                self.quoted_price()
+            };
+            // Buyers get what the sold token can represent and pay exactly that, rounded up to what the
+            // pay token can represent. The dust stays in `payment`.
+            let sold_divisibility = Self::divisibility(self.sold_token);
+            let pay_divisibility = Self::divisibility(pay_token);
+            let amount = (payment.amount() / price)
+                .checked_round(sold_divisibility, RoundingMode::ToZero)
+                .unwrap();
+            assert!(amount > Decimal::zero(), "Payment is too small to buy any tokens");
+            let liquidity_share = self
+                .config
+                .liquidity
+                .as_ref()
+                .map_or(Decimal::zero(), |liquidity| liquidity.share);
+            let liquidity_amount = (amount * liquidity_share)
+                .checked_round(sold_divisibility, RoundingMode::ToZero)
+                .unwrap();
+            assert!(
+                self.config.overflow || amount + liquidity_amount <= self.available_tokens(),
+                "Not enough tokens available for sale"
+            );
+
+            if self.in_whitelist_phase() {
+                self.check_whitelisted(&buyer);
+                let allocation = self.config.whitelist.as_ref().unwrap().allocation;
+                assert!(
+                    self.whitelist_sold + amount <= allocation,
+                    "Not enough tokens left in the whitelist allocation"
+                );
+                self.whitelist_sold += amount;
+            }
+
+            let paid_amount = (amount * price)
+                .checked_round(pay_divisibility, RoundingMode::ToPositiveInfinity)
+                .unwrap();
+            // Caps and limits count payments at their value in the unit of the sale price
+            let value = if pay_token == self.pay_token {
+                amount * self.quoted_price()
+            } else {
+                amount * self.price
+            };
+            let tier_allocation = self.tier_allocation(tier_proof);
+            self.check_purchase_limits(value, &buyer, tier_allocation);
+
+            self.pay_vaults.get_mut(&pay_token).unwrap().put(payment.take(paid_amount));
+            self.tokens_sold += amount;
+            self.purchase_count += 1;
+            if self.refunds_overpayments() {
+                self.unsettled_paid += paid_amount;
+                self.unsettled_tokens += amount;
+            }
+            // Only badges of the configured resource identify buyers; they were checked above
+            if let (Some(buyer), Some(_)) = (&buyer, self.config.buyer_badge) {
+                if self.buyers.get(buyer).is_none() {
+                    self.buyers.insert(buyer.clone(), ());
+                    self.unique_buyers += 1;
+                }
+            }
+            self.committed_tokens += amount + liquidity_amount;
+            self.liquidity_tokens += liquidity_amount;
+            self.liquidity_funds += (paid_amount * liquidity_share)
+                .checked_round(pay_divisibility, RoundingMode::ToZero)
+                .unwrap();
+            // An oversubscribed overflow sale can only ever hand out the tokens for sale
+            if self.config.overflow {
+                self.committed_tokens = self.committed_tokens.min(self.tokens_for_sale);
+            }
+            self.total_committed += value;
+            *self.raised_by_token.entry(pay_token).or_default() += paid_amount;
+            if self.available_tokens() == Decimal::zero() {
+                self.sold_out_price = Some(price);
+            }
+            if let Some(referrer) = &referrer {
+                self.credit_referrer(referrer, pay_token, paid_amount, value);
+            }
+
+            Purchase {
+                buyer,
+                pay_token,
+                amount,
+                paid_amount,
+                price,
+                referrer,
+            }
+        }
+
+        /// Returns the vested tokens and a refund bucket per pay token owed, together with the ids of
+        /// the purchase NFTs that are still vesting.
+        fn claim_purchases(
+            &mut self,
+            non_fungibles: Vec<NonFungible<PurchaseData>>,
+        ) -> (Vec<Bucket>, IndexSet<NonFungibleLocalId>) {
+            assert!(
+                self.cancelled
+                    || Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch >= self.end_time,
+                "Sale has not ended yet"
+            );
+
+            let mut tokens = Decimal::zero();
+            let mut refunds: IndexMap<ResourceAddress, Decimal> = IndexMap::new();
+            let mut vesting_ids = IndexSet::new();
+            for non_fungible in non_fungibles {
+                let (claimed, refund, fully_claimed) = self.claim_purchase(&non_fungible);
+                tokens += claimed;
+                if refund > Decimal::zero() {
+                    *refunds.entry(non_fungible.data().pay_token).or_default() += refund;
+                }
+                if !fully_claimed {
+                    vesting_ids.insert(non_fungible.local_id().clone());
+                }
+            }
+
+            self.total_claimed += tokens;
+            let mut buckets = vec![self.sold_token_vault.take(tokens)];
+            for (pay_token, refund) in refunds {
+                buckets.push(self.pay_vault(pay_token).take(refund));
+            }
+            (buckets, vesting_ids)
+        }
+
+        /// Settles and claims a single purchase NFT. Returns the tokens claimed, the pay tokens
+        /// refunded and whether the NFT is used up.
+        fn claim_purchase(&mut self, non_fungible: &NonFungible<PurchaseData>) -> (Decimal, Decimal, bool) {
+            let mut purchase_data = non_fungible.data();
+            let (amount, refund) = self.settlement(&purchase_data);
+
+            // A cancelled sale or one that missed its soft cap pays back what was spent instead, less
+            // the share of tokens already claimed, rounded down to what the pay token can represent
+            if self.refunding() {
+                // Refunded soulbound NFTs stay with their holder with nothing left to pay back
+                if purchase_data.paid_amount == Decimal::zero() {
+                    return (Decimal::zero(), Decimal::zero(), true);
+                }
+                let refund = if purchase_data.claimed_so_far == Decimal::zero() {
+                    purchase_data.paid_amount
+                } else {
+                    (purchase_data.paid_amount * (purchase_data.amount - purchase_data.claimed_so_far)
+                        / purchase_data.amount)
+                        .checked_round(Self::divisibility(purchase_data.pay_token), RoundingMode::ToZero)
+                        .unwrap()
+                };
+                self.committed_tokens -= amount - purchase_data.claimed_so_far;
+                // Soulbound NFTs are not burned, so they are marked as used up and refunded instead
+                if self.config.soulbound {
+                    self.launchpad_manager.authorize_with_amount(1, || {
+                        let id = non_fungible.local_id();
+                        self.purchase_nft.update_non_fungible_data(id, "claimed_so_far", purchase_data.amount);
+                        self.purchase_nft.update_non_fungible_data(id, "paid_amount", Decimal::zero());
+                    });
+                }
+                Runtime::emit_event(ClaimEvent {
+                    purchase_id: non_fungible.local_id().clone(),
+                    token_amount: Decimal::zero(),
+                    refund_amount: refund,
+                });
+                return (Decimal::zero(), refund, true);
+            }
+
+            // Overpayments from a clearing auction or an oversubscribed overflow sale are returned once
+            if !purchase_data.settled && self.refunds_overpayments() {
+                self.unsettled_purchases -= 1;
+                self.unsettled_paid -= purchase_data.paid_amount;
+                self.unsettled_tokens -= purchase_data.amount;
+                if self.funds_withdrawn {
+                    self.refund_reserve -= refund;
+                }
+                // Release the fraction of a token the fill was rounded down by
+                if self.config.overflow {
+                    self.committed_tokens -= purchase_data.amount * self.fill_ratio() - amount;
+                }
+                purchase_data.amount = amount;
+                purchase_data.paid_amount -= refund;
+                self.launchpad_manager.authorize_with_amount(1, || {
+                    let id = non_fungible.local_id();
+                    self.purchase_nft.update_non_fungible_data(id, "amount", amount);
+                    self.purchase_nft.update_non_fungible_data(id, "paid_amount", purchase_data.paid_amount);
+                    if amount > Decimal::zero() {
+                        self.purchase_nft.update_non_fungible_data(
+                            id,
+                            "price_paid",
+                            purchase_data.paid_amount / amount,
+                        );
+                    }
+                    self.purchase_nft.update_non_fungible_data(id, "settled", true);
+                });
+            }
+
+            let claimable = self.vested_amount(amount) - purchase_data.claimed_so_far;
+            let claimed_so_far = purchase_data.claimed_so_far + claimable;
+            self.committed_tokens -= claimable;
+
+            Runtime::emit_event(ClaimEvent {
+                purchase_id: non_fungible.local_id().clone(),
+                token_amount: claimable,
+                refund_amount: refund,
+            });
+
+            if claimed_so_far == amount && !self.config.soulbound {
+                return (claimable, refund, true);
+            }
+
+            self.launchpad_manager.authorize_with_amount(1, || {
+                self.purchase_nft.update_non_fungible_data(
+                    non_fungible.local_id(),
+                    "claimed_so_far",
+                    claimed_so_far,
+                )
+            });
+            (claimable, refund, claimed_so_far == amount)
+        }
+
+        /// Price of one sold token in the unit of `price`.
+        fn quoted_price(&self) -> Decimal {
+            if let Some(auction) = &self.config.dutch_auction {
+                return self.auction_price(auction);
+            }
+            match &self.config.whitelist {
+                Some(whitelist) if self.in_whitelist_phase() => whitelist.price,
+                _ => self.price,
+            }
+        }
+
+        /// USD price of one pay token, if the sale is oracle-priced.
+        fn oracle_rate(&self) -> Option<Decimal> {
+            let oracle = self.config.oracle.as_ref()?;
+            let component: Global<AnyComponent> = Global::from(oracle.component);
+            let (rate, updated_at): (Decimal, i64) = component.call("get_price", &(self.pay_token,));
+
+            assert!(rate > Decimal::zero(), "Oracle price must be greater than zero");
+            assert!(
+                self.current_time() - updated_at <= oracle.max_staleness,
+                "Oracle price is stale"
+            );
+            if let Some(reference_rate) = self.reference_oracle_rate {
+                let change = if rate > reference_rate {
+                    rate - reference_rate
+                } else {
+                    reference_rate - rate
+                };
+                assert!(
+                    change / reference_rate <= oracle.max_deviation,
+                    "Oracle price deviates too far from the reference rate"
+                );
+            }
+            Some(rate)
+        }
+
+        fn divisibility(resource: ResourceAddress) -> u8 {
+            ResourceManager::from(resource)
+                .resource_type()
+                .divisibility()
+                .unwrap_or(DIVISIBILITY_NONE)
+        }
+
+        fn pay_vault(&mut self, pay_token: ResourceAddress) -> &mut Vault {
+            self.pay_vaults.get_mut(&pay_token).unwrap()
+        }
+
+        fn refunding(&self) -> bool {
+            self.cancelled || self.sale_failed()
+        }
+
+        /// Tokens a purchase finally receives and the pay tokens owed back to it, once the sale ended.
+        /// The tokens are rounded down and the refund up to what the resources can represent.
+        fn settlement(&self, purchase_data: &PurchaseData) -> (Decimal, Decimal) {
+            if purchase_data.settled {
+                return (purchase_data.amount, Decimal::zero());
+            }
+            let round_refund = |refund: Decimal| {
+                refund
+                    .checked_round(Self::divisibility(purchase_data.pay_token), RoundingMode::ToPositiveInfinity)
+                    .unwrap()
+            };
+            if self.config.overflow {
+                let fill_ratio = self.fill_ratio();
+                return (
+                    (purchase_data.amount * fill_ratio)
+                        .checked_round(Self::divisibility(self.sold_token), RoundingMode::ToZero)
+                        .unwrap(),
+                    round_refund(purchase_data.paid_amount - purchase_data.paid_amount * fill_ratio),
+                );
+            }
+            match self.clearing_price() {
+                Some(clearing_price) => (
+                    purchase_data.amount,
+                    round_refund(purchase_data.paid_amount - purchase_data.amount * clearing_price),
+                ),
+                None => (purchase_data.amount, Decimal::zero()),
+            }
+        }
+
+        /// Whether purchases are settled after the sale, with part of their payment returned.
+        fn refunds_overpayments(&self) -> bool {
+            self.config.overflow
+                || self
+                    .config
+                    .dutch_auction
+                    .as_ref()
+                    .is_some_and(|auction| auction.uniform_clearing)
+        }
+
+        /// Rounded down to what the sold token can represent, so that every claim can be paid out.
+        fn vested_amount(&self, amount: Decimal) -> Decimal {
+            let vesting = match &self.config.vesting {
+                Some(vesting) => vesting,
+                None => return amount,
+            };
+
+            let tge_amount = amount * vesting.tge_unlock;
+            let since_cliff = self.current_time() - self.end_time - vesting.cliff;
+            let vested = if since_cliff < 0 {
+                tge_amount
+            } else {
+                let vested_time = match vesting.step {
+                    Some(step) => since_cliff - since_cliff % step,
+                    None => since_cliff,
+                };
+                if vested_time >= vesting.duration {
+                    return amount;
+                }
+                tge_amount
+                    + (amount - tge_amount) * Decimal::from(vested_time)
+                        / Decimal::from(vesting.duration)
+            };
+
+            vested
+                .checked_round(Self::divisibility(self.sold_token), RoundingMode::ToZero)
+                .unwrap()
+        }
+
+        fn auction_price(&self, auction: &DutchAuction) -> Decimal {
+            let duration = self.end_time - self.start_time;
+            let elapsed = (self.current_time() - self.start_time).clamp(0, duration);
+            let elapsed = match auction.step {
+                Some(step) => elapsed - elapsed % step,
+                None => elapsed,
+            };
+
+            auction.start_price
+                - (auction.start_price - self.price) * Decimal::from(elapsed) / Decimal::from(duration)
+        }
+
+        fn check_whitelisted(&self, buyer: &Option<NonFungibleGlobalId>) {
+            let buyer = buyer.as_ref().expect("A buyer badge is required during the whitelist phase");
+            let badge = self.config.whitelist.as_ref().unwrap().badge;
+            assert!(
+                badge == Some(buyer.resource_address()) || self.is_allowlisted(buyer.clone()),
+                "Buyer is not whitelisted"
+            );
+        }
+
+        fn tier_allocation(&self, tier_proof: Option<Proof>) -> Option<Decimal> {
+            let tiers = self.config.tiers.as_ref()?;
+            let proof = tier_proof.expect("A tier proof is required");
+            let held = match &tiers.token {
+                TierToken::Fungible(resource) => proof.check(*resource).amount(),
+                TierToken::StakingReceipt {
+                    resource,
+                    amount_field,
+                } => {
+                    let manager = ResourceManager::from(*resource);
+                    proof
+                        .check(*resource)
+                        .as_non_fungible()
+                        .non_fungible_local_ids()
+                        .iter()
+                        .fold(Decimal::zero(), |total, id| {
+                            total + Self::staked_amount(&manager, id, *amount_field)
+                        })
+                }
+            };
+
+            let allocation = self.allocation_for(held).unwrap();
+            assert!(allocation > Decimal::zero(), "Buyer does not qualify for any tier");
+            Some(allocation)
+        }
+
+        /// Reads the staked amount from a staking receipt of an arbitrary schema.
+        fn staked_amount(manager: &ResourceManager, id: &NonFungibleLocalId, amount_field: u8) -> Decimal {
+            match Self::data_field(manager, id, amount_field) {
+                Some(ScryptoValue::Custom {
+                    value: ScryptoCustomValue::Decimal(amount),
+                }) => amount,
+                Some(_) => panic!("Staking receipt amount is not a Decimal"),
+                None => panic!("Staking receipt has no amount field"),
+            }
+        }
+
+        /// Rejects purchases without a valid KYC credential from an allowed jurisdiction, if the sale
+        /// requires one.
+        fn check_kyc(&self, kyc_credential: Option<NonFungibleProof>) {
+            let kyc = match &self.config.kyc {
+                Some(kyc) => kyc,
+                None => return,
+            };
+            let credential = kyc_credential
+                .expect("A KYC credential is required")
+                .check_with_message(kyc.credential, "Invalid KYC credential");
+            let manager = ResourceManager::from(kyc.credential);
+            let id = credential.non_fungible_local_id();
+
+            let jurisdiction = match Self::data_field(&manager, &id, kyc.jurisdiction_field) {
+                Some(ScryptoValue::String { value }) => value,
+                _ => panic!("KYC credential has no jurisdiction"),
+            };
+            let expiry = match Self::data_field(&manager, &id, kyc.expiry_field) {
+                Some(ScryptoValue::I64 { value }) => value,
+                _ => panic!("KYC credential has no expiry"),
+            };
+            assert!(self.current_time() < expiry, "KYC credential has expired");
+            assert!(
+                !self.is_jurisdiction_blocked(jurisdiction),
+                "Purchases from this jurisdiction are blocked"
+            );
+        }
+
+        /// Field `index` of the data of a non-fungible with an arbitrary schema.
+        fn data_field(manager: &ResourceManager, id: &NonFungibleLocalId, index: u8) -> Option<ScryptoValue> {
+            let data: ScryptoValue = manager.call(
+                NON_FUNGIBLE_RESOURCE_MANAGER_GET_NON_FUNGIBLE_IDENT,
+                &NonFungibleResourceManagerGetNonFungibleInput { id: id.clone() },
+            );
+            match data {
+                ScryptoValue::Tuple { mut fields } if (index as usize) < fields.len() => {
+                    Some(fields.swap_remove(index as usize))
+                }
+                _ => None,
+            }
+        }
+
+        /// Divides the proceeds of a successful sale between the platform, the revenue recipients and
+        /// the owner. Runs once, on the first withdrawal after the sale.
+        fn distribute_proceeds(&mut self) {
+            assert!(
+                Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch >= self.end_time,
+                "Sale has not ended yet"
+            );
+            assert!(!self.sale_failed(), "Soft cap was not reached");
+            assert!(!self.cancelled, "Sale was cancelled");
+            if self.funds_withdrawn {
+                return;
+            }
+
+            // Settlement refunds and the liquidity reserve only ever arise in the main pay token
+            let outstanding_refunds = self.outstanding_refunds();
+            self.refund_reserve = outstanding_refunds;
+            self.funds_withdrawn = true;
+
+            for (pay_token, vault) in self.pay_vaults.iter() {
+                let referral_owed = self.referral_owed.get(pay_token).copied().unwrap_or_default();
+                let proceeds = if *pay_token == self.pay_token {
+                    vault.amount() - outstanding_refunds - referral_owed - self.liquidity_funds
+                } else {
+                    vault.amount() - referral_owed
+                };
+                // Shares are rounded down to what the pay token can represent; the owner gets the dust
+                let divisibility = Self::divisibility(*pay_token);
+                let share_of = |amount: Decimal, bps: u16| {
+                    (amount * Decimal::from(bps) / dec!(10000))
+                        .checked_round(divisibility, RoundingMode::ToZero)
+                        .unwrap()
+                };
+
+                let mut shares = vec![];
+                let mut after_fee = proceeds;
+                if let Some(platform_fee) = &self.config.platform_fee {
+                    let fee = share_of(proceeds, platform_fee.bps);
+                    after_fee -= fee;
+                    shares.push((platform_fee.recipient.clone(), fee));
+                }
+                for share in &self.config.revenue_split {
+                    shares.push((share.recipient.clone(), share_of(after_fee, share.bps)));
+                }
+
+                let mut owner_proceeds = proceeds;
+                for (recipient, amount) in shares {
+                    owner_proceeds -= amount;
+                    let mut owed = self
+                        .revenue_shares
+                        .get(&recipient)
+                        .map(|owed| owed.clone())
+                        .unwrap_or_default();
+                    *owed.entry(*pay_token).or_default() += amount;
+                    self.revenue_shares.insert(recipient, owed);
+                }
+                self.owner_proceeds.insert(*pay_token, owner_proceeds);
+            }
+        }
+
+        /// Credits the referrer of a purchase with their share of the payment, up to the referral cap.
+        fn credit_referrer(
+            &mut self,
+            referrer: &NonFungibleGlobalId,
+            pay_token: ResourceAddress,
+            paid_amount: Decimal,
+            value: Decimal,
+        ) {
+            let referral = self.config.referral.as_ref().expect("Sale has no referral rewards");
+            assert!(referrer.resource_address() == referral.badge, "Invalid referrer");
+            assert!(
+                ResourceManager::from(referral.badge).non_fungible_exists(referrer.local_id()),
+                "Unknown referrer"
+            );
+
+            let mut rewards = self
+                .referral_rewards
+                .get(referrer)
+                .map(|rewards| rewards.clone())
+                .unwrap_or_default();
+            let reward_value = (value * referral.reward).min(referral.cap - rewards.value);
+            if reward_value <= Decimal::zero() {
+                return;
+            }
+            let reward = (paid_amount * reward_value / value)
+                .checked_round(Self::divisibility(pay_token), RoundingMode::ToZero)
+                .unwrap();
+            rewards.value += reward_value;
+            *rewards.owed.entry(pay_token).or_default() += reward;
+            *self.referral_owed.entry(pay_token).or_default() += reward;
+            self.referral_rewards.insert(referrer.clone(), rewards);
+        }
+
+        /// `value` is the purchase valued in the unit of `price`. `tier_allocation` caps the
+        /// buyer's total spend.
+        fn check_purchase_limits(
+            &mut self,
+            value: Decimal,
+            buyer: &Option<NonFungibleGlobalId>,
+            tier_allocation: Option<Decimal>,
+        ) {
+            if let Some(hard_cap) = self.config.hard_cap {
+                assert!(
+                    self.total_committed + value <= hard_cap,
+                    "Purchase exceeds the hard cap"
+                );
+            }
+            if let Some(min_purchase) = self.config.min_purchase {
+                assert!(value >= min_purchase, "Purchase is below the minimum");
+            }
+            if let Some(max_purchase) = self.config.max_purchase {
+                assert!(value <= max_purchase, "Purchase is above the maximum");
+            }
+
+            if let Some(badge_resource) = self.config.buyer_badge {
+                let buyer = buyer.as_ref().expect("A buyer badge is required");
+                assert!(buyer.resource_address() == badge_resource, "Invalid buyer badge");
+
+                let spent = self.purchased_by(buyer.clone()) + value;
+                if let Some(max_per_buyer) = self.config.max_per_buyer {
+                    assert!(spent <= max_per_buyer, "Purchase exceeds the per-buyer limit");
+                }
+                if let Some(tier_allocation) = tier_allocation {
+                    assert!(spent <= tier_allocation, "Purchase exceeds the tier allocation");
+                }
+                self.buyer_purchases.insert(buyer.clone(), spent);
+            }
+        }
     }
 }
\ No newline at end of file
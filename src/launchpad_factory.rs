@@ -0,0 +1,137 @@
+use crate::launchpad::launchpad::{Launchpad, LaunchpadFunctions};
+use crate::{LaunchpadConfig, SalePhase};
+use scrypto::prelude::*;
+
+#[derive(ScryptoSbor, Clone)]
+struct LaunchpadEntry {
+    launchpad: Global<Launchpad>,
+    sold_token: ResourceAddress,
+    pay_token: ResourceAddress,
+}
+
+#[derive(ScryptoSbor, Clone, Debug)]
+pub struct LaunchpadSummary {
+    pub id: u64,
+    pub launchpad: ComponentAddress,
+    pub sold_token: ResourceAddress,
+    pub pay_token: ResourceAddress,
+    pub start_time: i64,
+    pub end_time: i64,
+    pub phase: SalePhase,
+}
+
+#[blueprint]
+mod launchpad_factory {
+
+    // Pages are capped because every entry reads the launchpad's live schedule and phase
+    const MAX_PAGE_SIZE: u64 = 50;
+
+    enable_method_auth! {
+        methods {
+            create_launchpad => restrict_to: [OWNER];
+            launchpad_count => PUBLIC;
+            get_launchpad => PUBLIC;
+            get_launchpads => PUBLIC;
+        }
+    }
+
+    struct LaunchpadFactory {
+        admin_badge: ResourceAddress,
+        launchpads: KeyValueStore<u64, LaunchpadEntry>,
+        launchpad_count: u64,
+    }
+
+    impl LaunchpadFactory {
+        /// Returns the factory together with its admin badge, which owns the factory and every
+        /// launchpad it creates.
+        pub fn instantiate_launchpad_factory() -> (Global<LaunchpadFactory>, FungibleBucket) {
+            let admin_badge = ResourceBuilder::new_fungible(OwnerRole::None)
+                .divisibility(DIVISIBILITY_NONE)
+                .metadata(metadata! {
+                    init {
+                        "name" => "Launchpad Factory Admin Badge", locked;
+                    }
+                })
+                .mint_initial_supply(1);
+
+            let owner_role = OwnerRole::Updatable(rule!(require(admin_badge.resource_address())));
+
+            let factory = Self {
+                admin_badge: admin_badge.resource_address(),
+                launchpads: KeyValueStore::new(),
+                launchpad_count: 0,
+            }
+            .instantiate()
+            .prepare_to_globalize(owner_role)
+            .globalize();
+
+            (factory, admin_badge)
+        }
+
+        pub fn create_launchpad(
+            &mut self,
+            start_time: i64,
+            end_time: i64,
+            sold_token: ResourceAddress,
+            pay_token: ResourceAddress,
+            price: Decimal,
+            sold_token_bucket: Bucket,
+            config: LaunchpadConfig,
+        ) -> Global<Launchpad> {
+            let launchpad = Blueprint::<Launchpad>::instantiate_launchpad(
+                rule!(require(self.admin_badge)),
+                start_time,
+                end_time,
+                sold_token,
+                pay_token,
+                price,
+                sold_token_bucket,
+                config,
+            );
+
+            self.launchpads.insert(
+                self.launchpad_count,
+                LaunchpadEntry {
+                    launchpad,
+                    sold_token,
+                    pay_token,
+                },
+            );
+            self.launchpad_count += 1;
+
+            launchpad
+        }
+
+        pub fn launchpad_count(&self) -> u64 {
+            self.launchpad_count
+        }
+
+        pub fn get_launchpad(&self, id: u64) -> Option<LaunchpadSummary> {
+            let entry = self.launchpads.get(&id)?.clone();
+            Some(Self::summarize(id, entry))
+        }
+
+        /// Returns up to `limit` launchpads starting at `offset`, oldest first.
+        pub fn get_launchpads(&self, offset: u64, limit: u64) -> Vec<LaunchpadSummary> {
+            let end = offset
+                .saturating_add(limit.min(MAX_PAGE_SIZE))
+                .min(self.launchpad_count);
+
+            (offset..end)
+                .filter_map(|id| self.get_launchpad(id))
+                .collect()
+        }
+
+        fn summarize(id: u64, entry: LaunchpadEntry) -> LaunchpadSummary {
+            LaunchpadSummary {
+                id,
+                launchpad: entry.launchpad.address(),
+                sold_token: entry.sold_token,
+                pay_token: entry.pay_token,
+                start_time: entry.launchpad.start_time(),
+                end_time: entry.launchpad.end_time(),
+                phase: entry.launchpad.phase(),
+            }
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use scrypto::prelude::*;
+
+// Minimal owner-fed price feed with the interface `Launchpad` expects from an oracle.
+#[blueprint]
+mod price_oracle {
+
+    enable_method_auth! {
+        methods {
+            set_price => restrict_to: [OWNER];
+            get_price => PUBLIC;
+        }
+    }
+
+    struct PriceOracle {
+        prices: KeyValueStore<ResourceAddress, (Decimal, i64)>,
+    }
+
+    impl PriceOracle {
+        pub fn instantiate_price_oracle(owner_rule: AccessRule) -> Global<PriceOracle> {
+            Self {
+                prices: KeyValueStore::new(),
+            }
+            .instantiate()
+            .prepare_to_globalize(OwnerRole::Updatable(owner_rule))
+            .globalize()
+        }
+
+        pub fn set_price(&mut self, resource: ResourceAddress, price: Decimal) {
+            let now = Clock::current_time_rounded_to_seconds().seconds_since_unix_epoch;
+            self.prices.insert(resource, (price, now));
+        }
+
+        /// Returns the USD price of `resource` and when it was last updated.
+        pub fn get_price(&self, resource: ResourceAddress) -> (Decimal, i64) {
+            *self.prices.get(&resource).expect("No price for resource")
+        }
+    }
+}
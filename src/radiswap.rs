@@ -49,7 +49,7 @@ mod radiswap{
                 vault_a: FungibleVault::with_bucket(bucket_a),
                 vault_b: FungibleVault::with_bucket(bucket_b),
                 pool_units_resource_manager: pool_units.resource_manager(),
-                fee: fee,
+                fee,
             }
             .instantiate()
             .prepare_to_globalize(OwnerRole::None)
@@ -1,3 +1,4 @@
+use scrypto_package::hello_test::*;
 use scrypto_test::{prelude::*, utils::dump_manifest_to_file_system};
 
 
@@ -1,180 +1,1990 @@
-use scrypto::prelude::ScryptoNonFungibleBucket;
-use scrypto_package::launchpad_test::Launchpad;
-use scrypto_package::*;
-use scrypto_test::{prelude::*, utils::dump_manifest_to_file_system};
-// use scrypto::prelude::*;
+use scrypto_package::{
+    ClaimEvent, OracleConfig, Tier, TierConfig, TierToken, DutchAuction, FundsWithdrawnEvent, KycConfig, LaunchpadConfig, LaunchpadCreatedEvent,
+    LaunchpadSummary, LiquidityBootstrappedEvent, LiquidityConfig, LiquidityPool, PurchaseEvent, PlatformFee, ReceiptDisplay, ReferralConfig, RevenueShare, RevenueShareWithdrawnEvent, ReferralRewardsClaimedEvent, SaleInfo, SalePhase, ScheduleChangedEvent, UnsoldWithdrawnEvent,
+    VestingSchedule, WhitelistPhase,
+};
+use scrypto_test::prelude::*;
+
+struct Sale {
+    component: ComponentAddress,
+    purchase_nft: ResourceAddress,
+}
+
+impl Sale {
+    fn from_receipt(receipt: &TransactionReceipt) -> Self {
+        let commit = receipt.expect_commit(true);
+        Sale {
+            component: commit.new_component_addresses()[0],
+            purchase_nft: commit.new_resource_addresses()[1],
+        }
+    }
+}
+
+enum TierProof {
+    Amount(ResourceAddress, Decimal),
+    NonFungibles(ResourceAddress, Vec<NonFungibleLocalId>),
+}
+
+/// Arguments of a `buy` call. Payment is in the sale's pay token unless `pay_token` is set.
+#[derive(Default)]
+struct BuyArgs {
+    amount: Decimal,
+    pay_token: Option<ResourceAddress>,
+    badge: Option<NonFungibleGlobalId>,
+    tier_proof: Option<TierProof>,
+    credential: Option<NonFungibleGlobalId>,
+    referrer: Option<NonFungibleGlobalId>,
+}
+
+impl From<Decimal> for BuyArgs {
+    fn from(amount: Decimal) -> Self {
+        Self {
+            amount,
+            ..Default::default()
+        }
+    }
+}
+
+struct TestLaunchpad {
+    ledger: DefaultLedgerSimulator,
+    public_key: Secp256k1PublicKey,
+    account: ComponentAddress,
+    package_address: PackageAddress,
+    /// Factory every sale is created through. Its admin badge owns the sales.
+    factory: ComponentAddress,
+    owner_badge: ResourceAddress,
+    sold_token: ResourceAddress,
+    pay_token: ResourceAddress,
+    round: u64,
+}
+
+impl TestLaunchpad {
+    fn new() -> Self {
+        // The default genesis has no validator, which advancing the round to move time requires
+        let genesis = CustomGenesis::default(
+            Epoch::of(1),
+            CustomGenesis::default_consensus_manager_config(),
+        );
+        let mut ledger = LedgerSimulatorBuilder::new()
+            .with_custom_genesis(genesis)
+            .build();
+        let (public_key, _private_key, account) = ledger.new_allocated_account();
+        let package_address = ledger.compile_and_publish(this_package!());
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_function(
+                package_address,
+                "LaunchpadFactory",
+                "instantiate_launchpad_factory",
+                manifest_args!(),
+            )
+            .deposit_batch(account)
+            .build();
+        let signer = NonFungibleGlobalId::from_public_key(&public_key);
+        let receipt = ledger.execute_manifest(manifest, vec![signer]);
+        let commit = receipt.expect_commit(true);
+        let factory = commit.new_component_addresses()[0];
+        let owner_badge = commit.new_resource_addresses()[0];
+
+        let mut test = Self {
+            ledger,
+            public_key,
+            account,
+            package_address,
+            factory,
+            owner_badge,
+            sold_token: XRD,
+            pay_token: XRD,
+            round: 1,
+        };
+        test.sold_token = test.new_token(dec!(100000));
+        test.pay_token = test.new_token(dec!(100000));
+        test
+    }
+
+    fn new_token(&mut self, supply: Decimal) -> ResourceAddress {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .new_token_fixed(OwnerRole::None, Default::default(), supply)
+            .deposit_batch(self.account)
+            .build();
+        let receipt = self.execute(manifest);
+        receipt.expect_commit(true).new_resource_addresses()[0]
+    }
+
+    fn execute(&mut self, manifest: TransactionManifestV1) -> TransactionReceipt {
+        self.ledger.execute_manifest(
+            manifest,
+            vec![NonFungibleGlobalId::from_public_key(&self.public_key)],
+        )
+    }
+
+    fn set_time(&mut self, seconds: i64) {
+        self.round += 1;
+        self.ledger
+            .advance_to_round_at_timestamp(Round::of(self.round), seconds * 1000)
+            .expect_commit_success();
+    }
+
+    fn balance(&mut self, resource: ResourceAddress) -> Decimal {
+        self.ledger.get_component_balance(self.account, resource)
+    }
+
+    fn instantiate_launchpad(
+        &mut self,
+        start_time: i64,
+        end_time: i64,
+        price: Decimal,
+        amount: Decimal,
+        config: LaunchpadConfig,
+    ) -> Sale {
+        let receipt = self.try_instantiate_launchpad(start_time, end_time, price, amount, config);
+        Sale::from_receipt(&receipt)
+    }
+
+    fn try_instantiate_launchpad(
+        &mut self,
+        start_time: i64,
+        end_time: i64,
+        price: Decimal,
+        amount: Decimal,
+        config: LaunchpadConfig,
+    ) -> TransactionReceipt {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(self.account, self.owner_badge, dec!(1))
+            .withdraw_from_account(self.account, self.sold_token, amount)
+            .take_from_worktop(self.sold_token, amount, "sold_token_bucket")
+            .call_method_with_name_lookup(self.factory, "create_launchpad", |lookup| {
+                (
+                    start_time,
+                    end_time,
+                    self.sold_token,
+                    self.pay_token,
+                    price,
+                    lookup.bucket("sold_token_bucket"),
+                    config.clone(),
+                )
+            })
+            .build();
+        self.execute(manifest)
+    }
+
+    fn buy(&mut self, sale: &Sale, args: impl Into<BuyArgs>) -> TransactionReceipt {
+        let BuyArgs {
+            amount,
+            pay_token,
+            badge,
+            tier_proof,
+            credential,
+            referrer,
+        } = args.into();
+        let pay_token = pay_token.unwrap_or(self.pay_token);
+        let mut builder = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(self.account, pay_token, amount)
+            .take_from_worktop(pay_token, amount, "payment");
+        if let Some(badge) = &badge {
+            builder = builder
+                .create_proof_from_account_of_non_fungibles(
+                    self.account,
+                    badge.resource_address(),
+                    [badge.local_id().clone()],
+                )
+                .pop_from_auth_zone("badge");
+        }
+        builder = match &tier_proof {
+            Some(TierProof::Amount(resource, amount)) => builder
+                .create_proof_from_account_of_amount(self.account, *resource, *amount)
+                .pop_from_auth_zone("tier_proof"),
+            Some(TierProof::NonFungibles(resource, ids)) => builder
+                .create_proof_from_account_of_non_fungibles(self.account, *resource, ids.clone())
+                .pop_from_auth_zone("tier_proof"),
+            None => builder,
+        };
+        if let Some(credential) = &credential {
+            builder = builder
+                .create_proof_from_account_of_non_fungibles(
+                    self.account,
+                    credential.resource_address(),
+                    [credential.local_id().clone()],
+                )
+                .pop_from_auth_zone("credential");
+        }
+        let manifest = builder
+            .call_method_with_name_lookup(sale.component, "buy", |lookup| {
+                (
+                    lookup.bucket("payment"),
+                    badge.as_ref().map(|_| lookup.proof("badge")),
+                    tier_proof.as_ref().map(|_| lookup.proof("tier_proof")),
+                    credential.as_ref().map(|_| lookup.proof("credential")),
+                    referrer,
+                )
+            })
+            .deposit_batch(self.account)
+            .build();
+        self.execute(manifest)
+    }
+
+    fn buy_more(&mut self, sale: &Sale, purchase_id: &NonFungibleLocalId, amount: Decimal) -> TransactionReceipt {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_non_fungibles(
+                self.account,
+                sale.purchase_nft,
+                [purchase_id.clone()],
+            )
+            .pop_from_auth_zone("purchase_nft")
+            .withdraw_from_account(self.account, self.pay_token, amount)
+            .take_from_worktop(self.pay_token, amount, "payment")
+            .call_method_with_name_lookup(sale.component, "buy_more", |lookup| {
+                (
+                    lookup.proof("purchase_nft"),
+                    lookup.bucket("payment"),
+                    None::<ManifestProof>,
+                    None::<ManifestProof>,
+                    None::<ManifestProof>,
+                    None::<NonFungibleGlobalId>,
+                )
+            })
+            .deposit_batch(self.account)
+            .build();
+        self.execute(manifest)
+    }
+
+    fn claim_all(&mut self, sale: &Sale) -> TransactionReceipt {
+        let amount = self.balance(sale.purchase_nft);
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(self.account, sale.purchase_nft, amount)
+            .take_all_from_worktop(sale.purchase_nft, "purchase_nft")
+            .call_method_with_name_lookup(sale.component, "claim", |lookup| {
+                (lookup.bucket("purchase_nft"),)
+            })
+            .deposit_batch(self.account)
+            .build();
+        self.execute(manifest)
+    }
+
+    fn claim_one(&mut self, sale: &Sale) -> TransactionReceipt {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .withdraw_from_account(self.account, sale.purchase_nft, dec!(1))
+            .take_all_from_worktop(sale.purchase_nft, "purchase_nft")
+            .call_method_with_name_lookup(sale.component, "claim", |lookup| {
+                (lookup.bucket("purchase_nft"),)
+            })
+            .deposit_batch(self.account)
+            .build();
+        self.execute(manifest)
+    }
+
+    fn withdraw_revenue_share(&mut self, sale: &Sale, recipient: &NonFungibleGlobalId) -> TransactionReceipt {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_non_fungibles(
+                self.account,
+                recipient.resource_address(),
+                [recipient.local_id().clone()],
+            )
+            .pop_from_auth_zone("recipient_badge")
+            .call_method_with_name_lookup(sale.component, "withdraw_revenue_share", |lookup| {
+                (lookup.proof("recipient_badge"),)
+            })
+            .deposit_batch(self.account)
+            .build();
+        self.execute(manifest)
+    }
+
+    fn call_owner_method(
+        &mut self,
+        sale: &Sale,
+        method: &str,
+        args: impl ResolvableArguments,
+    ) -> TransactionReceipt {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(self.account, self.owner_badge, dec!(1))
+            .call_method(sale.component, method, args)
+            .deposit_batch(self.account)
+            .build();
+        self.execute(manifest)
+    }
+
+    fn query<T: ScryptoDecode>(&mut self, sale: &Sale, method: &str) -> T {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_method(sale.component, method, manifest_args!())
+            .build();
+        let receipt = self.execute(manifest);
+        receipt.expect_commit_success().output(1)
+    }
+}
+
+fn soft_cap(soft_cap: Decimal) -> LaunchpadConfig {
+    LaunchpadConfig {
+        soft_cap: Some(soft_cap),
+        ..Default::default()
+    }
+}
 
 #[test]
 fn test_hello() {
-    // Setup the environment
-    let mut ledger = LedgerSimulatorBuilder::new().build();
-    println!("CHEEEECKK1");
+    let mut test = TestLaunchpad::new();
+    test.set_time(50);
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), LaunchpadConfig::default());
+
+    assert_eq!(test.query::<SalePhase>(&sale, "phase"), SalePhase::Upcoming);
+    assert_eq!(test.query::<Decimal>(&sale, "tokens_for_sale"), dec!(1000));
+    assert_eq!(test.query::<Decimal>(&sale, "available_tokens"), dec!(1000));
+    test.buy(&sale, dec!(500)).expect_commit_failure();
+}
+
+#[test]
+fn test_buy_reserves_tokens_and_rejects_overselling() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), LaunchpadConfig::default());
+
+    // 3000 pay tokens buy 600 of the 1000 sold tokens
+    test.buy(&sale, dec!(3000)).expect_commit_success();
+    assert_eq!(test.query::<Decimal>(&sale, "committed_tokens"), dec!(600));
+    assert_eq!(test.query::<Decimal>(&sale, "available_tokens"), dec!(400));
+
+    // Only 400 tokens are left, so another 600 cannot be sold
+    test.buy(&sale, dec!(3000)).expect_commit_failure();
+
+    test.set_time(200);
+    let sold_before = test.balance(test.sold_token);
+    test.call_owner_method(&sale, "withdraw_unsold_tokens", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(400));
+
+    // The buyer's tokens were not touched by the withdrawal
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(1000));
+    assert_eq!(test.query::<Decimal>(&sale, "sold_token_vault_amount"), dec!(0));
+}
+
+#[test]
+fn test_sale_below_soft_cap_refunds_buyers() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), soft_cap(dec!(4000)));
+
+    test.buy(&sale, dec!(3000)).expect_commit_success();
+    assert!(!test.query::<bool>(&sale, "sale_failed"));
+
+    test.set_time(200);
+    assert!(test.query::<bool>(&sale, "sale_failed"));
+    test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+        .expect_commit_failure();
+
+    // The receipt is burned and the full payment comes back
+    let pay_before = test.balance(test.pay_token);
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!(3000));
+    assert_eq!(test.balance(sale.purchase_nft), dec!(0));
+
+    // None of the sold tokens are owed anymore
+    let sold_before = test.balance(test.sold_token);
+    test.call_owner_method(&sale, "withdraw_unsold_tokens", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(1000));
+}
+
+#[test]
+fn test_sale_reaching_soft_cap_releases_funds() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), soft_cap(dec!(3000)));
 
-    // Create an account
-    let (public_key, _private_key, account) = ledger.new_allocated_account();
-    println!("CHEEEECKK2");
-    println!("public_key: {}", public_key);
+    test.buy(&sale, dec!(3000)).expect_commit_success();
 
-    // Publish package
-    let package_address = ledger.compile_and_publish(this_package!());
+    test.set_time(200);
+    assert!(!test.query::<bool>(&sale, "sale_failed"));
+    let pay_before = test.balance(test.pay_token);
+    test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!(3000));
 
-    // Get the current timestamp using Rust's standard library
-    use std::time::{SystemTime, UNIX_EPOCH};
+    // The raise stays recorded after the funds leave the vault
+    assert!(!test.query::<bool>(&sale, "sale_failed"));
+    assert_eq!(test.query::<Decimal>(&sale, "total_raised"), dec!(3000));
+}
+
+#[test]
+fn test_hard_cap_and_per_transaction_limits() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let config = LaunchpadConfig {
+        hard_cap: Some(dec!(4000)),
+        min_purchase: Some(dec!(100)),
+        max_purchase: Some(dec!(3000)),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+
+    test.buy(&sale, dec!(50)).expect_commit_failure();
+    test.buy(&sale, dec!(3500)).expect_commit_failure();
+    test.buy(&sale, dec!(3000)).expect_commit_success();
+
+    // 3000 + 1500 would overshoot the 4000 hard cap
+    test.buy(&sale, dec!(1500)).expect_commit_failure();
+    test.buy(&sale, dec!(1000)).expect_commit_success();
+    assert_eq!(test.query::<Decimal>(&sale, "total_raised"), dec!(4000));
+}
+
+#[test]
+fn test_per_buyer_limit_is_tracked_by_badge() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let badge = test.ledger.create_non_fungible_resource(test.account);
+    let config = LaunchpadConfig {
+        max_per_buyer: Some(dec!(2000)),
+        buyer_badge: Some(badge),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+    let first = NonFungibleGlobalId::new(badge, NonFungibleLocalId::integer(1));
+    let second = NonFungibleGlobalId::new(badge, NonFungibleLocalId::integer(2));
 
-    let current_timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_secs();
-    println!("current_timestamp: {}", current_timestamp);
+    test.buy(&sale, dec!(1500)).expect_commit_failure();
+    test.buy(&sale, BuyArgs {
+        amount: dec!(1500),
+        badge: Some(first.clone()),
+        ..Default::default()
+    }).expect_commit_success();
+    test.buy(&sale, BuyArgs {
+        amount: dec!(1000),
+        badge: Some(first.clone()),
+        ..Default::default()
+    }).expect_commit_failure();
+    test.buy(&sale, BuyArgs { amount: dec!(500), badge: Some(first.clone()), ..Default::default() })
+        .expect_commit_success();
+    test.buy(&sale, BuyArgs { amount: dec!(1000), badge: Some(second), ..Default::default() })
+        .expect_commit_success();
 
     let manifest = ManifestBuilder::new()
         .lock_fee_from_faucet()
-        .new_badge_fixed(OwnerRole::None, Default::default(), dec!(1))
-        .deposit_batch(account)
+        .call_method(sale.component, "purchased_by", manifest_args!(first))
         .build();
+    let spent: Decimal = test.execute(manifest).expect_commit_success().output(1);
+    assert_eq!(spent, dec!(2000));
+}
 
-    let receipt = ledger.execute_manifest(
-        manifest,
-        vec![NonFungibleGlobalId::from_public_key(&public_key)],
-    );
+#[test]
+fn test_whitelist_phase_admits_allowlisted_and_badge_holders() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let allowlisted = test.ledger.create_non_fungible_resource(test.account);
+    let whitelist_badge = test.ledger.create_non_fungible_resource(test.account);
+    let config = LaunchpadConfig {
+        whitelist: Some(WhitelistPhase {
+            end_time: 150,
+            price: dec!(4),
+            allocation: dec!(500),
+            badge: Some(whitelist_badge),
+        }),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+    let listed = NonFungibleGlobalId::new(allowlisted, NonFungibleLocalId::integer(1));
+    let unlisted = NonFungibleGlobalId::new(allowlisted, NonFungibleLocalId::integer(2));
+    let badge = NonFungibleGlobalId::new(whitelist_badge, NonFungibleLocalId::integer(1));
+
+    test.call_owner_method(&sale, "add_to_allowlist", manifest_args!(vec![listed.clone()]))
+        .expect_commit_success();
+    assert!(test.query::<bool>(&sale, "in_whitelist_phase"));
+    assert_eq!(test.query::<Decimal>(&sale, "current_price"), dec!(4));
+
+    test.buy(&sale, dec!(1200)).expect_commit_failure();
+    test.buy(&sale, BuyArgs { amount: dec!(1200), badge: Some(unlisted), ..Default::default() })
+        .expect_commit_failure();
+    test.buy(&sale, BuyArgs { amount: dec!(1200), badge: Some(listed), ..Default::default() })
+        .expect_commit_success();
+
+    // 300 of the 500 whitelist tokens are gone
+    test.buy(&sale, BuyArgs {
+        amount: dec!(1200),
+        badge: Some(badge.clone()),
+        ..Default::default()
+    }).expect_commit_failure();
+    test.buy(&sale, BuyArgs { amount: dec!(800), badge: Some(badge), ..Default::default() })
+        .expect_commit_success();
+    assert_eq!(test.query::<Decimal>(&sale, "whitelist_sold"), dec!(500));
+
+    // The public phase is open to everyone at the regular price
+    test.set_time(150);
+    assert_eq!(test.query::<Decimal>(&sale, "current_price"), dec!(5));
+    test.buy(&sale, dec!(1000)).expect_commit_success();
+    assert_eq!(test.query::<Decimal>(&sale, "committed_tokens"), dec!(700));
+}
+
+#[test]
+fn test_whitelist_must_use_the_buyer_badge() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let buyer_badge = test.ledger.create_non_fungible_resource(test.account);
+    let whitelist_badge = test.ledger.create_non_fungible_resource(test.account);
+    let config = |badge| LaunchpadConfig {
+        buyer_badge: Some(buyer_badge),
+        whitelist: Some(WhitelistPhase {
+            end_time: 150,
+            price: dec!(4),
+            allocation: dec!(500),
+            badge: Some(badge),
+        }),
+        ..Default::default()
+    };
+    test.try_instantiate_launchpad(100, 200, dec!(5), dec!(1000), config(whitelist_badge))
+        .expect_commit_failure();
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config(buyer_badge));
+
+    // Only buyer badges can be allowlisted, as the same proof must pass both checks
+    let other = NonFungibleGlobalId::new(whitelist_badge, NonFungibleLocalId::integer(1));
+    test.call_owner_method(&sale, "add_to_allowlist", manifest_args!(vec![other]))
+        .expect_commit_failure();
+}
+
+#[test]
+fn test_vesting_releases_tokens_over_time() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let config = LaunchpadConfig {
+        vesting: Some(VestingSchedule {
+            tge_unlock: dec!("0.2"),
+            cliff: 100,
+            duration: 200,
+            step: Some(50),
+        }),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+    test.buy(&sale, dec!(3000)).expect_commit_success();
+    let sold_before = test.balance(test.sold_token);
+
+    // 20% of the 600 tokens unlock at the end of the sale
+    test.set_time(200);
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(120));
+    assert_eq!(test.balance(sale.purchase_nft), dec!(1));
+
+    // Nothing more before the cliff
+    test.set_time(250);
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(120));
+
+    // 75 seconds past the cliff only counts one full 50 second step
+    test.set_time(375);
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(240));
+
+    // Fully vested: the rest is paid out and the receipt is burned
+    test.set_time(600);
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(600));
+    assert_eq!(test.balance(sale.purchase_nft), dec!(0));
+    assert_eq!(test.query::<Decimal>(&sale, "committed_tokens"), dec!(0));
+}
+
+#[test]
+fn test_vesting_pays_whole_units_of_an_indivisible_token() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    test.sold_token = test.ledger.create_fungible_resource(dec!(1000), DIVISIBILITY_NONE, test.account);
+    let config = LaunchpadConfig {
+        vesting: Some(VestingSchedule {
+            tge_unlock: dec!("0.2"),
+            cliff: 0,
+            duration: 100,
+            step: None,
+        }),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+    test.buy(&sale, dec!(15)).expect_commit_success();
+    let sold_before = test.balance(test.sold_token);
+
+    // 0.6 tokens unlock at the end of the sale and 1.8 halfway through, rounded down
+    test.set_time(200);
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(0));
+    test.set_time(250);
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(1));
 
-    receipt.expect_commit_success();
-    let owner_badge_address = receipt.expect_commit(true).new_resource_addresses()[0];
-    println!("owner_badge_address: {}", owner_badge_address.to_hex());
+    test.set_time(300);
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(3));
+    assert_eq!(test.balance(sale.purchase_nft), dec!(0));
+}
+
+#[test]
+fn test_factory_creates_and_indexes_launchpads() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(50);
 
-    let sold_token_manifest = ManifestBuilder::new()
+    let manifest = ManifestBuilder::new()
         .lock_fee_from_faucet()
-        .new_token_fixed(OwnerRole::None, Default::default(), dec!(100000))
-        .deposit_batch(account)
+        .call_function(
+            test.package_address,
+            "LaunchpadFactory",
+            "instantiate_launchpad_factory",
+            manifest_args!(),
+        )
+        .deposit_batch(test.account)
         .build();
+    let receipt = test.execute(manifest);
+    let commit = receipt.expect_commit(true);
+    let factory = commit.new_component_addresses()[0];
+    let admin_badge = commit.new_resource_addresses()[0];
 
-    let receipt = ledger.execute_manifest(
-        sold_token_manifest,
-        vec![NonFungibleGlobalId::from_public_key(&public_key)],
-    );
+    let mut launchpads = vec![];
+    for (start_time, end_time) in [(100i64, 200i64), (40, 60)] {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(test.account, admin_badge, dec!(1))
+            .withdraw_from_account(test.account, test.sold_token, dec!(1000))
+            .take_from_worktop(test.sold_token, dec!(1000), "sold_token_bucket")
+            .call_method_with_name_lookup(factory, "create_launchpad", |lookup| {
+                (
+                    start_time,
+                    end_time,
+                    test.sold_token,
+                    test.pay_token,
+                    dec!(5),
+                    lookup.bucket("sold_token_bucket"),
+                    LaunchpadConfig::default(),
+                )
+            })
+            .build();
+        let receipt = test.execute(manifest);
+        launchpads.push(receipt.expect_commit(true).new_component_addresses()[0]);
+    }
+
+    // Only the factory admin can create launchpads
+    let manifest = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .withdraw_from_account(test.account, test.sold_token, dec!(1000))
+        .take_from_worktop(test.sold_token, dec!(1000), "sold_token_bucket")
+        .call_method_with_name_lookup(factory, "create_launchpad", |lookup| {
+            (
+                100i64,
+                200i64,
+                test.sold_token,
+                test.pay_token,
+                dec!(5),
+                lookup.bucket("sold_token_bucket"),
+                LaunchpadConfig::default(),
+            )
+        })
+        .build();
+    test.execute(manifest).expect_commit_failure();
+
+    // Nor can a launchpad be created around the factory
+    let manifest = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .withdraw_from_account(test.account, test.sold_token, dec!(1000))
+        .take_from_worktop(test.sold_token, dec!(1000), "sold_token_bucket")
+        .call_function_with_name_lookup(
+            test.package_address,
+            "Launchpad",
+            "instantiate_launchpad",
+            |lookup| {
+                (
+                    rule!(allow_all),
+                    100i64,
+                    200i64,
+                    test.sold_token,
+                    test.pay_token,
+                    dec!(5),
+                    lookup.bucket("sold_token_bucket"),
+                    LaunchpadConfig::default(),
+                )
+            },
+        )
+        .deposit_batch(test.account)
+        .build();
+    test.execute(manifest).expect_commit_failure();
 
-    receipt.expect_commit_success();
-    let sold_token_address = receipt.expect_commit(true).new_resource_addresses()[0];
-    // Create a Bech32 encoder for the simulator network
-    let bech32_encoder = AddressBech32Encoder::new(&NetworkDefinition::simulator());
+    let manifest = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .call_method(factory, "launchpad_count", manifest_args!())
+        .call_method(factory, "get_launchpads", manifest_args!(0u64, 10u64))
+        .call_method(factory, "get_launchpads", manifest_args!(1u64, 10u64))
+        .build();
+    let receipt = test.execute(manifest);
+    let commit = receipt.expect_commit_success();
+    assert_eq!(commit.output::<u64>(1), 2);
 
-    // Encode the address
-    let encoded_address = bech32_encoder.encode(&sold_token_address.to_vec());
+    let page: Vec<LaunchpadSummary> = commit.output(2);
+    assert_eq!(page.len(), 2);
+    assert_eq!(page[0].id, 0);
+    assert_eq!(page[0].launchpad, launchpads[0]);
+    assert_eq!(page[0].phase, SalePhase::Upcoming);
+    assert_eq!((page[1].start_time, page[1].end_time), (40, 60));
+    assert_eq!(page[1].phase, SalePhase::Active);
 
-    println!("sold_token_address: {}", encoded_address.unwrap());
+    let page: Vec<LaunchpadSummary> = commit.output(3);
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].launchpad, launchpads[1]);
 
-    let pay_token_manifest = ManifestBuilder::new()
+    // The registry reports each sale's live phase
+    test.set_time(60);
+    let manifest = ManifestBuilder::new()
         .lock_fee_from_faucet()
-        .new_token_fixed(OwnerRole::None, Default::default(), dec!(100000))
-        .deposit_batch(account)
+        .call_method(factory, "get_launchpad", manifest_args!(1u64))
         .build();
+    let receipt = test.execute(manifest);
+    let summary: Option<LaunchpadSummary> = receipt.expect_commit_success().output(1);
+    assert_eq!(summary.unwrap().phase, SalePhase::Ended);
+}
 
-    let receipt = ledger.execute_manifest(
-        pay_token_manifest,
-        vec![NonFungibleGlobalId::from_public_key(&public_key)],
-    );
+#[test]
+fn test_dutch_auction_price_descends_to_the_floor() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let auction = |step| LaunchpadConfig {
+        dutch_auction: Some(DutchAuction {
+            start_price: dec!(10),
+            step,
+            uniform_clearing: false,
+        }),
+        ..Default::default()
+    };
+    let linear = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), auction(None));
+    let stepped = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), auction(Some(30)));
+    assert_eq!(test.query::<Decimal>(&linear, "current_price"), dec!(10));
+
+    test.set_time(150);
+    assert_eq!(test.query::<Decimal>(&linear, "current_price"), dec!("7.5"));
+    assert_eq!(test.query::<Decimal>(&stepped, "current_price"), dec!("8.5"));
+
+    // Buyers pay the price at the time of purchase: 750 pay tokens buy 100 sold tokens
+    test.buy(&linear, dec!(750)).expect_commit_success();
+    assert_eq!(test.query::<Decimal>(&linear, "committed_tokens"), dec!(100));
+
+    test.set_time(199);
+    assert_eq!(test.query::<Decimal>(&stepped, "current_price"), dec!("5.5"));
+
+    test.set_time(250);
+    assert_eq!(test.query::<Decimal>(&linear, "current_price"), dec!(5));
+}
+
+#[test]
+fn test_uniform_clearing_auction_refunds_the_difference() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let config = LaunchpadConfig {
+        dutch_auction: Some(DutchAuction {
+            start_price: dec!(10),
+            step: None,
+            uniform_clearing: true,
+        }),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+
+    // 100 tokens at 10, then the remaining 900 at 7.5 sell the auction out
+    test.buy(&sale, dec!(1000)).expect_commit_success();
+    assert_eq!(test.query::<Option<Decimal>>(&sale, "clearing_price"), None);
+    test.set_time(150);
+    test.buy(&sale, dec!(6750)).expect_commit_success();
+    assert_eq!(test.query::<Option<Decimal>>(&sale, "clearing_price"), Some(dec!("7.5")));
+
+    // Two smallest units per unsettled purchase cover rounding each refund up
+    test.set_time(200);
+    assert_eq!(test.query::<Decimal>(&sale, "total_committed"), dec!(7750));
+    assert_eq!(test.query::<Decimal>(&sale, "total_raised"), dec!(7500));
+    assert_eq!(test.query::<Decimal>(&sale, "outstanding_refunds"), dec!("250.000000000000000004"));
+
+    let pay_before = test.balance(test.pay_token);
+    let sold_before = test.balance(test.sold_token);
+    test.claim_one(&sale).expect_commit_success();
+    test.claim_one(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!(250));
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(1000));
+    assert_eq!(test.query::<Decimal>(&sale, "outstanding_refunds"), dec!(0));
+
+    // The owner receives exactly the clearing price for every token sold
+    test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!(7750));
+    assert_eq!(test.query::<Decimal>(&sale, "pay_token_vault_amount"), dec!(0));
+}
+
+#[test]
+fn test_clearing_auction_fails_when_its_net_raise_misses_the_soft_cap() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let config = LaunchpadConfig {
+        soft_cap: Some(dec!(1500)),
+        dutch_auction: Some(DutchAuction {
+            start_price: dec!(10),
+            step: None,
+            uniform_clearing: true,
+        }),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+
+    // 1750 is bid for 200 tokens, but at the floor of 5 the sale only keeps 1000
+    let pay_before = test.balance(test.pay_token);
+    test.buy(&sale, dec!(1000)).expect_commit_success();
+    test.set_time(150);
+    test.buy(&sale, dec!(750)).expect_commit_success();
+    assert_eq!(test.query::<Decimal>(&sale, "total_raised"), dec!(1750));
+
+    test.set_time(200);
+    assert_eq!(test.query::<Option<Decimal>>(&sale, "clearing_price"), Some(dec!(5)));
+    assert!(test.query::<bool>(&sale, "sale_failed"));
+    test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+        .expect_commit_failure();
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.pay_token), pay_before);
+}
+
+#[test]
+fn test_clearing_auction_ended_early_clears_at_its_last_price() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let config = LaunchpadConfig {
+        hard_cap: Some(dec!(2500)),
+        dutch_auction: Some(DutchAuction {
+            start_price: dec!(10),
+            step: None,
+            uniform_clearing: true,
+        }),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+
+    // 100 tokens at 10 and 200 at 7.5 reach the hard cap halfway through
+    test.buy(&sale, dec!(1000)).expect_commit_success();
+    test.set_time(150);
+    test.buy(&sale, dec!(1500)).expect_commit_success();
+    test.call_owner_method(&sale, "end_sale_early", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.query::<Option<Decimal>>(&sale, "clearing_price"), Some(dec!("7.5")));
+
+    // Only the first buyer overpaid, by 2.5 per token
+    let pay_before = test.balance(test.pay_token);
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!(250));
+}
+
+#[test]
+fn test_overflow_sale_fills_commitments_pro_rata() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let config = LaunchpadConfig {
+        overflow: true,
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+
+    // 10000 pay tokens are committed for 1000 tokens worth 5000
+    test.buy(&sale, dec!(6000)).expect_commit_success();
+    test.buy(&sale, dec!(4000)).expect_commit_success();
+    assert_eq!(test.query::<Decimal>(&sale, "total_committed"), dec!(10000));
+    assert_eq!(test.query::<Decimal>(&sale, "total_raised"), dec!(5000));
+    assert_eq!(test.query::<Decimal>(&sale, "committed_tokens"), dec!(1000));
+    assert_eq!(test.query::<Decimal>(&sale, "fill_ratio"), dec!("0.5"));
+
+    test.set_time(200);
+    assert_eq!(test.query::<Decimal>(&sale, "outstanding_refunds"), dec!("5000.000000000000000004"));
+
+    // Each commitment gets half its tokens and half its payment back
+    let pay_before = test.balance(test.pay_token);
+    let sold_before = test.balance(test.sold_token);
+    test.claim_one(&sale).expect_commit_success();
+    test.claim_one(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(1000));
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!(5000));
+
+    test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!(10000));
+    assert_eq!(test.query::<Decimal>(&sale, "pay_token_vault_amount"), dec!(0));
+    assert_eq!(test.query::<Decimal>(&sale, "sold_token_vault_amount"), dec!(0));
+}
+
+#[test]
+fn test_overflow_settlement_rounds_fills_down_and_refunds_up() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    test.sold_token = test.ledger.create_fungible_resource(dec!(1000), DIVISIBILITY_NONE, test.account);
+    test.pay_token = test.ledger.create_fungible_resource(dec!(1000000), 2, test.account);
+    let config = LaunchpadConfig {
+        overflow: true,
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(1), dec!(10), config);
+
+    // 21 tokens are committed for 10, so every commitment is filled 3.33 tokens
+    for _ in 0..3 {
+        test.buy(&sale, dec!(7)).expect_commit_success();
+    }
+
+    // Each refund of 3.666.. is rounded up to 3.67, so the owner leaves room for all of them
+    test.set_time(200);
+    assert_eq!(test.query::<Decimal>(&sale, "outstanding_refunds"), dec!("11.07"));
+    let pay_before = test.balance(test.pay_token);
+    let sold_before = test.balance(test.sold_token);
+    test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!("9.93"));
+
+    for _ in 0..3 {
+        test.claim_one(&sale).expect_commit_success();
+    }
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(3) * 3);
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!("9.93") + dec!("3.67") * 3);
+    assert_eq!(test.query::<Decimal>(&sale, "outstanding_refunds"), dec!(0));
+
+    // What the rounding left of the reserve is swept to the owner
+    test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!(21));
+    assert_eq!(test.query::<Decimal>(&sale, "pay_token_vault_amount"), dec!(0));
+}
+
+#[test]
+fn test_state_changes_emit_events() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let receipt = test.try_instantiate_launchpad(100, 200, dec!(5), dec!(1000), LaunchpadConfig::default());
+    let sale = Sale::from_receipt(&receipt);
+    let created: Vec<LaunchpadCreatedEvent> =
+        test.ledger.extract_events_of_type(receipt.expect_commit_success());
+    assert_eq!(created.len(), 1);
+    assert_eq!(created[0].launchpad, sale.component);
+    assert_eq!(created[0].tokens_for_sale, dec!(1000));
+
+    let receipt = test.buy(&sale, dec!(3000));
+    let purchases: Vec<PurchaseEvent> =
+        test.ledger.extract_events_of_type(receipt.expect_commit_success());
+    assert_eq!(purchases.len(), 1);
+    assert_eq!(purchases[0].buyer, None);
+    assert_eq!(purchases[0].pay_amount, dec!(3000));
+    assert_eq!(purchases[0].token_amount, dec!(600));
+    assert_eq!(purchases[0].price, dec!(5));
+
+    test.set_time(200);
+    let receipt = test.claim_all(&sale);
+    let claims: Vec<ClaimEvent> = test.ledger.extract_events_of_type(receipt.expect_commit_success());
+    assert_eq!(claims.len(), 1);
+    assert_eq!(claims[0].purchase_id, purchases[0].purchase_id);
+    assert_eq!(claims[0].token_amount, dec!(600));
+    assert_eq!(claims[0].refund_amount, dec!(0));
+
+    let receipt = test.call_owner_method(&sale, "withdraw_funds", manifest_args!());
+    let withdrawals: Vec<FundsWithdrawnEvent> =
+        test.ledger.extract_events_of_type(receipt.expect_commit_success());
+    assert_eq!(withdrawals[0].amount, dec!(3000));
+
+    let receipt = test.call_owner_method(&sale, "withdraw_unsold_tokens", manifest_args!());
+    let withdrawals: Vec<UnsoldWithdrawnEvent> =
+        test.ledger.extract_events_of_type(receipt.expect_commit_success());
+    assert_eq!(withdrawals[0].amount, dec!(400));
+}
+
+#[test]
+fn test_pause_and_cancel_refund_buyers() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), LaunchpadConfig::default());
+    let pay_before = test.balance(test.pay_token);
+    test.buy(&sale, dec!(1000)).expect_commit_success();
+
+    // Only the owner can pause
+    let manifest = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .call_method(sale.component, "pause", manifest_args!())
+        .build();
+    test.execute(manifest).expect_commit_failure();
+
+    test.call_owner_method(&sale, "pause", manifest_args!())
+        .expect_commit_success();
+    test.buy(&sale, dec!(500)).expect_commit_failure();
+    test.call_owner_method(&sale, "unpause", manifest_args!())
+        .expect_commit_success();
+    test.buy(&sale, dec!(500)).expect_commit_success();
+
+    test.call_owner_method(&sale, "cancel_sale", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.query::<SalePhase>(&sale, "phase"), SalePhase::Cancelled);
+    test.buy(&sale, dec!(500)).expect_commit_failure();
+
+    // Buyers get back exactly what they spent without waiting for the end of the sale
+    test.claim_one(&sale).expect_commit_success();
+    test.claim_one(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.pay_token), pay_before);
+
+    let sold_before = test.balance(test.sold_token);
+    test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+        .expect_commit_failure();
+    test.call_owner_method(&sale, "withdraw_unsold_tokens", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(1000));
+}
 
-    receipt.expect_commit_success();
-    let pay_token_address = receipt.expect_commit(true).new_resource_addresses()[0];
-    // println!("pay_token_address: {}", pay_token_address);
+#[test]
+fn test_emergency_refund_after_the_sale_ended() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let config = LaunchpadConfig {
+        vesting: Some(VestingSchedule {
+            tge_unlock: dec!("0.5"),
+            cliff: 0,
+            duration: 100,
+            step: None,
+        }),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+    let withdrawn = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), LaunchpadConfig::default());
+    test.buy(&sale, dec!(1000)).expect_commit_success();
+    test.buy(&withdrawn, dec!(1000)).expect_commit_success();
+    test.call_owner_method(&sale, "emergency_refund", manifest_args!())
+        .expect_commit_failure();
+
+    // Half of the purchase is claimed at the end before the sale is unwound
+    test.set_time(200);
+    let sold_before = test.balance(test.sold_token);
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(100));
+
+    test.call_owner_method(&sale, "emergency_refund", manifest_args!())
+        .expect_commit_success();
+    let pay_before = test.balance(test.pay_token);
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!(500));
+    test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+        .expect_commit_failure();
+
+    // Once the proceeds are gone there is nothing left to refund from
+    test.call_owner_method(&withdrawn, "withdraw_funds", manifest_args!())
+        .expect_commit_success();
+    test.call_owner_method(&withdrawn, "emergency_refund", manifest_args!())
+        .expect_commit_failure();
+}
+
+#[test]
+fn test_partial_refunds_fit_the_pay_token() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    test.pay_token = test.ledger.create_fungible_resource(dec!(1000000), 2, test.account);
+    let config = LaunchpadConfig {
+        vesting: Some(VestingSchedule {
+            tge_unlock: dec!("0.5"),
+            cliff: 0,
+            duration: 100,
+            step: None,
+        }),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(3), dec!(1000), config);
+    test.buy(&sale, dec!(10)).expect_commit_success();
+
+    // Half of 3.33.. tokens is claimed, so the refund of the rest is just above 5 before rounding
+    test.set_time(200);
+    test.claim_all(&sale).expect_commit_success();
+    test.call_owner_method(&sale, "emergency_refund", manifest_args!())
+        .expect_commit_success();
+    let pay_before = test.balance(test.pay_token);
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!(5));
+}
+
+#[test]
+fn test_owner_can_reschedule_extend_and_end_early() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(50);
+    let config = LaunchpadConfig {
+        hard_cap: Some(dec!(1000)),
+        max_end_time: Some(300),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+
+    let receipt = test.call_owner_method(&sale, "reschedule", manifest_args!(120i64, 220i64));
+    let events: Vec<ScheduleChangedEvent> =
+        test.ledger.extract_events_of_type(receipt.expect_commit_success());
+    assert_eq!((events[0].start_time, events[0].end_time), (120, 220));
+    assert_eq!(test.query::<i64>(&sale, "start_time"), 120);
+
+    // The new window must not start in the past or end after the maximum
+    test.call_owner_method(&sale, "reschedule", manifest_args!(40i64, 220i64))
+        .expect_commit_failure();
+    test.call_owner_method(&sale, "reschedule", manifest_args!(120i64, 301i64))
+        .expect_commit_failure();
+
+    // Once the sale runs it can only be extended
+    test.set_time(120);
+    test.call_owner_method(&sale, "reschedule", manifest_args!(130i64, 220i64))
+        .expect_commit_failure();
+    test.call_owner_method(&sale, "extend_sale", manifest_args!(301i64))
+        .expect_commit_failure();
+    test.call_owner_method(&sale, "extend_sale", manifest_args!(250i64))
+        .expect_commit_success();
+    assert_eq!(test.query::<i64>(&sale, "end_time"), 250);
+
+    test.buy(&sale, dec!(500)).expect_commit_success();
+    test.call_owner_method(&sale, "end_sale_early", manifest_args!())
+        .expect_commit_failure();
+    test.buy(&sale, dec!(500)).expect_commit_success();
+    test.call_owner_method(&sale, "end_sale_early", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.query::<i64>(&sale, "end_time"), 120);
+    assert_eq!(test.query::<SalePhase>(&sale, "phase"), SalePhase::Ended);
+
+    test.claim_one(&sale).expect_commit_success();
+}
+
+#[test]
+fn test_purchases_in_several_currencies() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let stablecoin = test.new_token(dec!(100000));
+    let config = LaunchpadConfig {
+        soft_cap: Some(dec!(2000)),
+        extra_pay_tokens: indexmap!(stablecoin => dec!(2)),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config.clone());
+    let failed = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+
+    // 1000 of the pay token and 400 of the stablecoin buy 200 tokens each
+    test.buy(&sale, dec!(1000)).expect_commit_success();
+    test.buy(&sale, BuyArgs {
+        amount: dec!(400),
+        pay_token: Some(stablecoin),
+        ..Default::default()
+    }).expect_commit_success();
+    test.buy(&sale, BuyArgs {
+        amount: dec!(400),
+        pay_token: Some(XRD),
+        ..Default::default()
+    }).expect_commit_failure();
+    assert_eq!(test.query::<Decimal>(&sale, "committed_tokens"), dec!(400));
+    assert_eq!(test.query::<Decimal>(&sale, "total_raised"), dec!(2000));
+    let manifest = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .call_method(sale.component, "raised_in", manifest_args!(stablecoin))
+        .build();
+    let raised: Decimal = test.execute(manifest).expect_commit_success().output(1);
+    assert_eq!(raised, dec!(400));
+
+    test.buy(&failed, dec!(500)).expect_commit_success();
+    test.buy(&failed, BuyArgs {
+        amount: dec!(200),
+        pay_token: Some(stablecoin),
+        ..Default::default()
+    }).expect_commit_success();
+
+    test.set_time(200);
+    let pay_before = test.balance(test.pay_token);
+    let stable_before = test.balance(stablecoin);
+    test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!(1000));
+    assert_eq!(test.balance(stablecoin) - stable_before, dec!(400));
 
-    // Test the `instantiate_hello` function.
+    // Refunds are paid back in the currency each purchase was made with
+    test.claim_one(&sale).expect_commit_success();
+    test.claim_one(&sale).expect_commit_success();
+    let pay_before = test.balance(test.pay_token);
+    let stable_before = test.balance(stablecoin);
+    test.claim_one(&failed).expect_commit_success();
+    test.claim_one(&failed).expect_commit_success();
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!(500));
+    assert_eq!(test.balance(stablecoin) - stable_before, dec!(200));
+}
+
+#[test]
+fn test_oracle_priced_sale_converts_usd_to_the_pay_token() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
     let manifest = ManifestBuilder::new()
         .lock_fee_from_faucet()
-        .withdraw_from_account(account, sold_token_address, dec!(1000))
-        .take_from_worktop(sold_token_address, dec!(1000), "sold_token_bucket")
-        .create_proof_from_account_of_amount(account, owner_badge_address, dec!(1))
         .call_function(
-            package_address,
-            "Launchpad",
-            "instantiate_launchpad",
-            manifest_args!(
-                current_timestamp,
-                current_timestamp + 100,
-                sold_token_address,
-                pay_token_address,
-                dec!(5),
-                "sold_token_bucket"
-            ),
+            test.package_address,
+            "PriceOracle",
+            "instantiate_price_oracle",
+            manifest_args!(rule!(require(test.owner_badge))),
+        )
+        .build();
+    let receipt = test.execute(manifest);
+    let oracle = receipt.expect_commit(true).new_component_addresses()[0];
+    let set_price = |test: &mut TestLaunchpad, price: Decimal| {
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_amount(test.account, test.owner_badge, dec!(1))
+            .call_method(oracle, "set_price", manifest_args!(test.pay_token, price))
+            .build();
+        test.execute(manifest).expect_commit_success();
+    };
+
+    // Tokens sell for 1 USD, paid in a pay token worth 0.02 USD
+    let config = LaunchpadConfig {
+        oracle: Some(OracleConfig {
+            component: oracle,
+            max_staleness: 60,
+            max_deviation: dec!("0.1"),
+        }),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 300, dec!(1), dec!(1000), config);
+    set_price(&mut test, dec!("0.02"));
+    assert_eq!(test.query::<Decimal>(&sale, "current_price"), dec!(50));
+    test.buy(&sale, dec!(5000)).expect_commit_success();
+    assert_eq!(test.query::<Decimal>(&sale, "committed_tokens"), dec!(100));
+
+    // A 25% jump from the rate of the last purchase is rejected, a 5% move is not
+    set_price(&mut test, dec!("0.025"));
+    test.buy(&sale, dec!(2000)).expect_commit_failure();
+    set_price(&mut test, dec!("0.021"));
+    test.buy(&sale, dec!(2000)).expect_commit_success();
+    assert_eq!(test.query::<Decimal>(&sale, "committed_tokens"), dec!(142));
+    assert_eq!(test.query::<Decimal>(&sale, "total_raised"), dec!(142));
+
+    // The price must have been updated within the last minute
+    test.set_time(170);
+    test.buy(&sale, dec!(2000)).expect_commit_failure();
+
+    // After a lasting 19% move the owner re-anchors the check and the sale carries on
+    set_price(&mut test, dec!("0.025"));
+    test.buy(&sale, dec!(2000)).expect_commit_failure();
+    test.call_owner_method(&sale, "reset_oracle_rate", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.query::<Decimal>(&sale, "current_price"), dec!(40));
+    test.buy(&sale, dec!(2000)).expect_commit_success();
+    assert_eq!(test.query::<Decimal>(&sale, "committed_tokens"), dec!(192));
+}
+
+#[derive(ScryptoSbor, ManifestSbor)]
+struct StakeReceipt {
+    pool: String,
+    staked: Decimal,
+}
+
+impl NonFungibleData for StakeReceipt {
+    const MUTABLE_FIELDS: &'static [&'static str] = &[];
+}
+
+#[test]
+fn test_tiers_cap_allocation_by_platform_holdings() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let badge = test.ledger.create_non_fungible_resource(test.account);
+    let first = NonFungibleGlobalId::new(badge, NonFungibleLocalId::integer(1));
+    let second = NonFungibleGlobalId::new(badge, NonFungibleLocalId::integer(2));
+    let platform_token = test.new_token(dec!(100000));
+
+    let manifest = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .create_non_fungible_resource(
+            OwnerRole::None,
+            NonFungibleIdType::Integer,
+            false,
+            NonFungibleResourceRoles::default(),
+            metadata!(),
+            Some([
+                (
+                    NonFungibleLocalId::integer(1),
+                    StakeReceipt { pool: "main".to_string(), staked: dec!(600) },
+                ),
+                (
+                    NonFungibleLocalId::integer(2),
+                    StakeReceipt { pool: "main".to_string(), staked: dec!(500) },
+                ),
+            ]),
         )
+        .deposit_batch(test.account)
         .build();
+    let receipt = test.execute(manifest);
+    let staking_receipt = receipt.expect_commit(true).new_resource_addresses()[0];
+
+    let tiers = |token| LaunchpadConfig {
+        buyer_badge: Some(badge),
+        tiers: Some(TierConfig {
+            token,
+            base_allocation: dec!(500),
+            tiers: vec![
+                Tier { threshold: dec!(100), multiplier: dec!(1) },
+                Tier { threshold: dec!(1000), multiplier: dec!(3) },
+            ],
+        }),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), tiers(TierToken::Fungible(platform_token)));
+    let staked_sale = test.instantiate_launchpad(
+        100,
+        200,
+        dec!(5),
+        dec!(1000),
+        tiers(TierToken::StakingReceipt { resource: staking_receipt, amount_field: 1 }),
+    );
+
+    // Holding 150 platform tokens allows 500, holding 1000 allows 1500 in total
+    let holding = |amount| Some(TierProof::Amount(platform_token, amount));
+    test.buy(&sale, BuyArgs { amount: dec!(500), badge: Some(first.clone()), ..Default::default() })
+        .expect_commit_failure();
+    test.buy(&sale, BuyArgs {
+        amount: dec!(500),
+        badge: Some(first.clone()),
+        tier_proof: holding(dec!(50)),
+        ..Default::default()
+    }).expect_commit_failure();
+    test.buy(&sale, BuyArgs {
+        amount: dec!(500),
+        badge: Some(first.clone()),
+        tier_proof: holding(dec!(150)),
+        ..Default::default()
+    }).expect_commit_success();
+    test.buy(&sale, BuyArgs {
+        amount: dec!(5),
+        badge: Some(first.clone()),
+        tier_proof: holding(dec!(150)),
+        ..Default::default()
+    }).expect_commit_failure();
+    test.buy(&sale, BuyArgs {
+        amount: dec!(1000),
+        badge: Some(first.clone()),
+        tier_proof: holding(dec!(1000)),
+        ..Default::default()
+    }).expect_commit_success();
+    test.buy(&sale, BuyArgs {
+        amount: dec!(5),
+        badge: Some(first),
+        tier_proof: holding(dec!(1000)),
+        ..Default::default()
+    }).expect_commit_failure();
+
+    // Staking receipts of 600 and 500 reach the second tier only together
+    let staked = |ids: &[u64]| {
+        let ids = ids.iter().map(|id| NonFungibleLocalId::integer(*id)).collect();
+        Some(TierProof::NonFungibles(staking_receipt, ids))
+    };
+    test.buy(&staked_sale, BuyArgs {
+        amount: dec!(500),
+        badge: Some(second.clone()),
+        tier_proof: staked(&[1]),
+        ..Default::default()
+    }).expect_commit_success();
+    test.buy(&staked_sale, BuyArgs {
+        amount: dec!(5),
+        badge: Some(second.clone()),
+        tier_proof: staked(&[1]),
+        ..Default::default()
+    }).expect_commit_failure();
+    test.buy(&staked_sale, BuyArgs {
+        amount: dec!(1000),
+        badge: Some(second.clone()),
+        tier_proof: staked(&[1,
+        2]),
+        ..Default::default()
+    }).expect_commit_success();
+    test.buy(&staked_sale, BuyArgs {
+        amount: dec!(5),
+        badge: Some(second),
+        tier_proof: staked(&[1,
+        2]),
+        ..Default::default()
+    }).expect_commit_failure();
+}
+
+#[test]
+fn test_buy_more_adds_to_a_receipt_and_claim_takes_many() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), LaunchpadConfig::default());
+
+    let receipt = test.buy(&sale, dec!(500));
+    let purchase_id = test.ledger.extract_events_of_type::<PurchaseEvent>(receipt.expect_commit_success())[0]
+        .purchase_id
+        .clone();
+
+    // Buying more adds 200 tokens to the same receipt instead of minting a new one
+    let receipt = test.buy_more(&sale, &purchase_id, dec!(1000));
+    let events = test.ledger.extract_events_of_type::<PurchaseEvent>(receipt.expect_commit_success());
+    assert_eq!(events[0].purchase_id, purchase_id);
+    assert_eq!(events[0].token_amount, dec!(200));
+    assert_eq!(test.balance(sale.purchase_nft), dec!(1));
+    assert_eq!(test.query::<Decimal>(&sale, "committed_tokens"), dec!(300));
+
+    // Buying more still respects the available supply
+    test.buy_more(&sale, &purchase_id, dec!(3600)).expect_commit_failure();
+
+    test.buy(&sale, dec!(250)).expect_commit_success();
+    assert_eq!(test.balance(sale.purchase_nft), dec!(2));
+
+    // Both receipts are claimed and burned in one call
+    test.set_time(200);
+    let sold_before = test.balance(test.sold_token);
+    let receipt = test.claim_all(&sale);
+    let events = test.ledger.extract_events_of_type::<ClaimEvent>(receipt.expect_commit_success());
+    assert_eq!(events.len(), 2);
+    assert_eq!(test.balance(test.sold_token) - sold_before, dec!(350));
+    assert_eq!(test.balance(sale.purchase_nft), dec!(0));
+    assert_eq!(test.query::<Decimal>(&sale, "committed_tokens"), dec!(0));
+}
+
+#[derive(ScryptoSbor)]
+struct PurchaseReceipt {
+    amount: Decimal,
+    pay_token: ResourceAddress,
+    paid_amount: Decimal,
+    claimed_so_far: Decimal,
+    settled: bool,
+    price_paid: Decimal,
+    purchased_at: i64,
+    launchpad: ComponentAddress,
+    name: String,
+    key_image_url: UncheckedUrl,
+}
+
+impl NonFungibleData for PurchaseReceipt {
+    const MUTABLE_FIELDS: &'static [&'static str] =
+        &["amount", "paid_amount", "claimed_so_far", "settled", "price_paid"];
+}
 
-    let receipt = ledger.execute_manifest(
-        manifest,
-        vec![NonFungibleGlobalId::from_public_key(&public_key)],
+#[test]
+fn test_purchase_nft_shows_purchase_details() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let receipt = ReceiptDisplay {
+        name: "Example Sale Receipt".to_string(),
+        description: "Example sale".to_string(),
+        icon_url: Some(UncheckedUrl::of("https://example.com/icon.png")),
+        info_url: Some(UncheckedUrl::of("https://example.com")),
+        key_image_url: None,
+    };
+    let config = LaunchpadConfig { receipt, ..Default::default() };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+
+    assert_eq!(
+        test.ledger.get_metadata(sale.purchase_nft.into(), "description"),
+        Some(MetadataValue::String("Example sale".to_string()))
+    );
+    assert_eq!(
+        test.ledger.get_metadata(sale.purchase_nft.into(), "info_url"),
+        Some(MetadataValue::Url(UncheckedUrl::of("https://example.com")))
     );
-    println!("{:?}\n", receipt);
-    let component = receipt.expect_commit(true).new_component_addresses()[0];
-    // println!("component: {}", component);
-
-    // // Test the `free_token` method.
-    // let manifest = ManifestBuilder::new()
-    //     .lock_fee_from_faucet()
-    //     .withdraw_from_account(account, pay_token_address, dec!(1000))
-    //     .take_from_worktop(pay_token_address, dec!(1000), "pay_token_bucket")
-    //     .call_method(component, "buy", manifest_args!("pay_token_bucket"))
-    //     .call_method(
-    //         account,
-    //         "deposit_batch",
-    //         manifest_args!(ManifestExpression::EntireWorktop),
-    //     )
-    //     .build();
-    // let receipt = ledger.execute_manifest(
-    //     manifest,
-    //     vec![NonFungibleGlobalId::from_public_key(&public_key)],
-    // );
-    // println!("{:?}\n", receipt);
-    // receipt.expect_commit_success();
-}
-
-/*
-#[test]
-fn test_hello_with_test_environment() -> Result<(), RuntimeError> {
-    // Arrange
-    let mut env = TestEnvironment::new();
-    let package_address =
-        PackageFactory::compile_and_publish(this_package!(), &mut env, CompileProfile::Fast)?;
-
-    let sold_token = ResourceBuilder::new_fungible(OwnerRole::None)
-    .mint_initial_supply(dec!(1000000000000000000), &mut env)?;
-
-    let pay_token = ResourceBuilder::new_fungible(OwnerRole::None)
-    .mint_initial_supply(dec!(1000000000000000000), &mut env)?;
-
-    // Get the current time
-    let current_time = env.get_current_time().seconds_since_unix_epoch;
-    println!("current_time: {}", current_time);
-
-    let mut launchpad = Launchpad::instantiate_launchpad(
-        current_time,
-        current_time + 100,
-        sold_token.resource_address(&mut env).unwrap(),
-        pay_token.resource_address(&mut env).unwrap(),
-        dec!(100),
-        sold_token,
-        package_address,
-        &mut env,
-    )?;
-
-    let pay_bucket = pay_token.take(dec!(10000), &mut env).unwrap();
-
-    // Act
-    let (nft_bucket, payment) = launchpad.buy(pay_bucket, &mut env)?;
-    println!("CHECK 1");
-    println!("nft_bucket: {}", nft_bucket.non_fungible_local_id().to_string());
-    println!("CHECK 2");
-    let vault_amount = launchpad.pay_token_vault_amount(&mut env).unwrap();
-    println!("vault_amount: {}", vault_amount);
-    assert_eq!(vault_amount, dec!("10000"));
-
-    env.set_current_time(Instant::new(current_time + 200));
-    println!("current_time: {}", env.get_current_time().seconds_since_unix_epoch);
-
-    let bucket = launchpad.claim(nft_bucket, &mut env)?;
-    println!("sold token remaining: {}", launchpad.sold_token_vault_amount(&mut env).unwrap());
-    let amount = bucket.amount(&mut env)?;
-    println!("amount bought: {}", amount);
-    assert_eq!(amount, dec!("100"));
-
-    Ok(())
-} */
+
+    test.set_time(150);
+    let receipt = test.buy(&sale, dec!(500));
+    let purchase_id = test.ledger.extract_events_of_type::<PurchaseEvent>(receipt.expect_commit_success())[0]
+        .purchase_id
+        .clone();
+    test.buy_more(&sale, &purchase_id, dec!(1000)).expect_commit_success();
+
+    let data: PurchaseReceipt = test.ledger.get_non_fungible_data(sale.purchase_nft, purchase_id);
+    assert_eq!(data.amount, dec!(300));
+    assert_eq!(data.pay_token, test.pay_token);
+    assert_eq!(data.paid_amount, dec!(1500));
+    assert_eq!(data.price_paid, dec!(5));
+    assert_eq!(data.purchased_at, 150);
+    assert_eq!(data.launchpad, sale.component);
+    assert_eq!(data.name, "Example Sale Receipt");
+    // Without a dedicated image the NFT shows the resource icon
+    assert_eq!(data.key_image_url, UncheckedUrl::of("https://example.com/icon.png"));
+}
+
+#[test]
+fn test_referrers_earn_a_capped_share_of_referred_purchases() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let referrer_badge = test.ledger.create_non_fungible_resource(test.account);
+    let config = LaunchpadConfig {
+        referral: Some(ReferralConfig {
+            badge: referrer_badge,
+            reward: dec!("0.1"),
+            cap: dec!(150),
+        }),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+    let referrer = NonFungibleGlobalId::new(referrer_badge, NonFungibleLocalId::integer(1));
+    let pay_token = test.pay_token;
+
+    // The second purchase only earns the 50 left under the cap
+    test.buy(&sale, BuyArgs {
+        amount: dec!(1000),
+        referrer: Some(referrer.clone()),
+        ..Default::default()
+    }).expect_commit_success();
+    test.buy(&sale, BuyArgs {
+        amount: dec!(1000),
+        referrer: Some(referrer.clone()),
+        ..Default::default()
+    }).expect_commit_success();
+    let unknown = NonFungibleGlobalId::new(referrer_badge, NonFungibleLocalId::integer(99));
+    test.buy(&sale, BuyArgs { amount: dec!(1000), referrer: Some(unknown), ..Default::default() })
+        .expect_commit_failure();
+
+    let manifest = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .call_method(sale.component, "referral_rewards", manifest_args!(referrer.clone()))
+        .build();
+    let rewards: IndexMap<ResourceAddress, Decimal> = test.execute(manifest).expect_commit_success().output(1);
+    assert_eq!(rewards.get(&pay_token), Some(&dec!(150)));
+
+    let account = test.account;
+    let claim_manifest = || {
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_non_fungibles(
+                account,
+                referrer_badge,
+                [NonFungibleLocalId::integer(1)],
+            )
+            .pop_from_auth_zone("referrer_badge")
+            .call_method_with_name_lookup(sale.component, "claim_referral_rewards", |lookup| {
+                (lookup.proof("referrer_badge"),)
+            })
+            .deposit_batch(account)
+            .build()
+    };
+    test.execute(claim_manifest()).expect_commit_failure();
+
+    // The rewards are held back from the proceeds
+    test.set_time(200);
+    let pay_before = test.balance(pay_token);
+    test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.balance(pay_token) - pay_before, dec!(1850));
+
+    let receipt = test.execute(claim_manifest());
+    let events = test.ledger.extract_events_of_type::<ReferralRewardsClaimedEvent>(receipt.expect_commit_success());
+    assert_eq!(events[0].referrer, referrer);
+    assert_eq!(events[0].amount, dec!(150));
+    assert_eq!(test.balance(pay_token) - pay_before, dec!(2000));
+    assert_eq!(test.query::<Decimal>(&sale, "pay_token_vault_amount"), dec!(0));
+}
+
+#[test]
+fn test_referral_rewards_fit_the_pay_token_and_block_emergency_refunds() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    test.pay_token = test.ledger.create_fungible_resource(dec!(100000), 6, test.account);
+    let referrer_badge = test.ledger.create_non_fungible_resource(test.account);
+    let config = LaunchpadConfig {
+        referral: Some(ReferralConfig {
+            badge: referrer_badge,
+            reward: dec!("0.1"),
+            cap: dec!(150),
+        }),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(3), dec!(1000), config);
+    let referrer = NonFungibleGlobalId::new(referrer_badge, NonFungibleLocalId::integer(1));
+    test.buy(&sale, BuyArgs { amount: dec!(10), referrer: Some(referrer), ..Default::default() })
+        .expect_commit_success();
+
+    test.set_time(200);
+    let manifest = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .create_proof_from_account_of_non_fungibles(
+            test.account,
+            referrer_badge,
+            [NonFungibleLocalId::integer(1)],
+        )
+        .pop_from_auth_zone("referrer_badge")
+        .call_method_with_name_lookup(sale.component, "claim_referral_rewards", |lookup| {
+            (lookup.proof("referrer_badge"),)
+        })
+        .deposit_batch(test.account)
+        .build();
+    let receipt = test.execute(manifest);
+    let events = test.ledger.extract_events_of_type::<ReferralRewardsClaimedEvent>(receipt.expect_commit_success());
+    assert!(events[0].amount > dec!(0) && events[0].amount <= dec!("0.1"));
+
+    test.call_owner_method(&sale, "emergency_refund", manifest_args!())
+        .expect_commit_failure();
+    test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.query::<Decimal>(&sale, "pay_token_vault_amount"), dec!(0));
+}
+
+#[test]
+fn test_platform_fee_and_revenue_split_are_withdrawn_independently() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let badge = test.ledger.create_non_fungible_resource(test.account);
+    let platform = NonFungibleGlobalId::new(badge, NonFungibleLocalId::integer(1));
+    let treasury = NonFungibleGlobalId::new(badge, NonFungibleLocalId::integer(2));
+    let stranger = NonFungibleGlobalId::new(badge, NonFungibleLocalId::integer(3));
+    let config = LaunchpadConfig {
+        platform_fee: Some(PlatformFee { bps: 250, recipient: platform.clone() }),
+        revenue_split: vec![RevenueShare { recipient: treasury.clone(), bps: 5000 }],
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+    let pay_token = test.pay_token;
+
+    test.buy(&sale, dec!(2000)).expect_commit_success();
+    test.withdraw_revenue_share(&sale, &treasury).expect_commit_failure();
+
+    // 2.5% of 2000 goes to the platform and half of the remaining 1950 to the treasury
+    test.set_time(200);
+    let pay_before = test.balance(pay_token);
+    let receipt = test.withdraw_revenue_share(&sale, &treasury);
+    let events = test.ledger.extract_events_of_type::<RevenueShareWithdrawnEvent>(receipt.expect_commit_success());
+    assert_eq!(events[0].recipient, treasury);
+    assert_eq!(events[0].amount, dec!(975));
+    assert_eq!(test.balance(pay_token) - pay_before, dec!(975));
+
+    test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+        .expect_commit_success();
+    assert_eq!(test.balance(pay_token) - pay_before, dec!(1950));
+
+    let manifest = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .call_method(sale.component, "revenue_share", manifest_args!(platform.clone()))
+        .build();
+    let owed: IndexMap<ResourceAddress, Decimal> = test.execute(manifest).expect_commit_success().output(1);
+    assert_eq!(owed.get(&pay_token), Some(&dec!(50)));
+
+    test.withdraw_revenue_share(&sale, &platform).expect_commit_success();
+    assert_eq!(test.balance(pay_token) - pay_before, dec!(2000));
+    assert_eq!(test.query::<Decimal>(&sale, "pay_token_vault_amount"), dec!(0));
+
+    test.withdraw_revenue_share(&sale, &stranger).expect_commit_failure();
+}
+
+#[test]
+fn test_liquidity_is_bootstrapped_into_a_locked_pool() {
+    for pool in [LiquidityPool::Radiswap { fee: dec!("0.003") }, LiquidityPool::TwoResourcePool] {
+        let mut test = TestLaunchpad::new();
+        test.set_time(100);
+        let config = LaunchpadConfig {
+            liquidity: Some(LiquidityConfig {
+                share: dec!("0.2"),
+                pool,
+                lock_duration: 1000,
+            }),
+            ..Default::default()
+        };
+        let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+        let pay_token = test.pay_token;
+
+        // 400 tokens bought reserve another 80 for the pool, so 1000 cannot be covered
+        test.buy(&sale, dec!(2000)).expect_commit_success();
+        test.buy(&sale, dec!(4500)).expect_commit_failure();
+        assert_eq!(test.query::<(Decimal, Decimal)>(&sale, "reserved_liquidity"), (dec!(80), dec!(400)));
+
+        test.set_time(200);
+        let pay_before = test.balance(pay_token);
+        test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+            .expect_commit_success();
+        assert_eq!(test.balance(pay_token) - pay_before, dec!(1600));
+
+        let manifest = ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .call_method(sale.component, "bootstrap_liquidity", manifest_args!())
+            .build();
+        let receipt = test.execute(manifest);
+        let commit = receipt.expect_commit_success();
+        let pool_units = commit.new_resource_addresses()[0];
+        let events = test.ledger.extract_events_of_type::<LiquidityBootstrappedEvent>(commit);
+        assert_eq!(events[0].token_amount, dec!(80));
+        assert_eq!(events[0].pay_amount, dec!(400));
+        assert_eq!(events[0].unlock_time, 1200);
+        assert_eq!(test.query::<Option<ComponentAddress>>(&sale, "liquidity_pool"), Some(events[0].pool));
+        assert_eq!(test.query::<Decimal>(&sale, "pay_token_vault_amount"), dec!(0));
+        assert_eq!(test.query::<Decimal>(&sale, "available_tokens"), dec!(520));
+
+        test.call_owner_method(&sale, "withdraw_pool_units", manifest_args!())
+            .expect_commit_failure();
+        test.set_time(1200);
+        test.call_owner_method(&sale, "withdraw_pool_units", manifest_args!())
+            .expect_commit_success();
+        assert!(test.balance(pool_units) > dec!(0));
+    }
+}
+
+#[test]
+fn test_soulbound_receipts_stay_with_the_buyer() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let config = LaunchpadConfig {
+        soulbound: true,
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+    let receipt = test.buy(&sale, dec!(500));
+    let purchase_id = test.ledger.extract_events_of_type::<PurchaseEvent>(receipt.expect_commit_success())[0]
+        .purchase_id
+        .clone();
+
+    let (_, _, other_account) = test.ledger.new_allocated_account();
+    let manifest = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .withdraw_non_fungibles_from_account(test.account, sale.purchase_nft, [purchase_id.clone()])
+        .try_deposit_entire_worktop_or_abort(other_account, None)
+        .build();
+    test.execute(manifest).expect_commit_failure();
+
+    // Claiming with a proof leaves the used-up receipt in the account
+    test.set_time(200);
+    let account = test.account;
+    let claim_manifest = || {
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_non_fungibles(account, sale.purchase_nft, [purchase_id.clone()])
+            .pop_from_auth_zone("purchase_nft")
+            .call_method_with_name_lookup(sale.component, "claim_bound", |lookup| {
+                (lookup.proof("purchase_nft"),)
+            })
+            .deposit_batch(account)
+            .build()
+    };
+    let sold_token = test.sold_token;
+    let tokens_before = test.balance(sold_token);
+    test.execute(claim_manifest()).expect_commit_success();
+    assert_eq!(test.balance(sold_token) - tokens_before, dec!(100));
+    assert_eq!(test.balance(sale.purchase_nft), dec!(1));
+
+    test.execute(claim_manifest()).expect_commit_success();
+    assert_eq!(test.balance(sold_token) - tokens_before, dec!(100));
+}
+
+#[test]
+fn test_soulbound_receipts_are_refunded_only_once() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let config = LaunchpadConfig {
+        soulbound: true,
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+    let receipt = test.buy(&sale, dec!(500));
+    let purchase_id = test.ledger.extract_events_of_type::<PurchaseEvent>(receipt.expect_commit_success())[0]
+        .purchase_id
+        .clone();
+    test.buy(&sale, dec!(500)).expect_commit_success();
+    test.call_owner_method(&sale, "cancel_sale", manifest_args!())
+        .expect_commit_success();
+
+    // Claiming the same receipt again pays nothing out of the other buyer's refund
+    let account = test.account;
+    let claim_manifest = || {
+        ManifestBuilder::new()
+            .lock_fee_from_faucet()
+            .create_proof_from_account_of_non_fungibles(account, sale.purchase_nft, [purchase_id.clone()])
+            .pop_from_auth_zone("purchase_nft")
+            .call_method_with_name_lookup(sale.component, "claim_bound", |lookup| {
+                (lookup.proof("purchase_nft"),)
+            })
+            .deposit_batch(account)
+            .build()
+    };
+    let pay_before = test.balance(test.pay_token);
+    test.execute(claim_manifest()).expect_commit_success();
+    test.execute(claim_manifest()).expect_commit_success();
+    assert_eq!(test.balance(test.pay_token) - pay_before, dec!(500));
+    assert_eq!(test.query::<Decimal>(&sale, "pay_token_vault_amount"), dec!(500));
+    assert_eq!(test.query::<Decimal>(&sale, "committed_tokens"), dec!(100));
+    assert_eq!(test.query::<Decimal>(&sale, "available_tokens"), dec!(900));
+}
+
+#[test]
+fn test_transferable_receipts_cannot_be_claimed_in_place() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), LaunchpadConfig::default());
+    let receipt = test.buy(&sale, dec!(500));
+    let purchase_id = test.ledger.extract_events_of_type::<PurchaseEvent>(receipt.expect_commit_success())[0]
+        .purchase_id
+        .clone();
+
+    test.set_time(200);
+    let manifest = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .create_proof_from_account_of_non_fungibles(test.account, sale.purchase_nft, [purchase_id])
+        .pop_from_auth_zone("purchase_nft")
+        .call_method_with_name_lookup(sale.component, "claim_bound", |lookup| {
+            (lookup.proof("purchase_nft"),)
+        })
+        .deposit_batch(test.account)
+        .build();
+    test.execute(manifest.clone()).expect_commit_failure();
+    test.execute(manifest).expect_commit_failure();
+    assert_eq!(test.query::<Decimal>(&sale, "sold_token_vault_amount"), dec!(1000));
+}
+
+#[derive(ScryptoSbor)]
+struct KycCredential {
+    jurisdiction: String,
+    expires_at: i64,
+}
+
+impl NonFungibleData for KycCredential {
+    const MUTABLE_FIELDS: &'static [&'static str] = &[];
+}
+
+#[test]
+fn test_kyc_credentials_gate_purchases_by_jurisdiction_and_expiry() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let credential = |jurisdiction: &str, expires_at| KycCredential {
+        jurisdiction: jurisdiction.to_string(),
+        expires_at,
+    };
+    let manifest = ManifestBuilder::new()
+        .lock_fee_from_faucet()
+        .create_non_fungible_resource(
+            OwnerRole::None,
+            NonFungibleIdType::Integer,
+            false,
+            NonFungibleResourceRoles::default(),
+            metadata!(),
+            Some([
+                (NonFungibleLocalId::integer(1), credential("CH", 1000)),
+                (NonFungibleLocalId::integer(2), credential("US", 1000)),
+                (NonFungibleLocalId::integer(3), credential("CH", 150)),
+            ]),
+        )
+        .deposit_batch(test.account)
+        .build();
+    let receipt = test.execute(manifest);
+    let kyc_credential = receipt.expect_commit(true).new_resource_addresses()[0];
+    let config = LaunchpadConfig {
+        kyc: Some(KycConfig {
+            credential: kyc_credential,
+            jurisdiction_field: 0,
+            expiry_field: 1,
+            blocked_jurisdictions: vec!["US".to_string()],
+        }),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+    let holder = |id| Some(NonFungibleGlobalId::new(kyc_credential, NonFungibleLocalId::integer(id)));
+
+    test.buy(&sale, dec!(500)).expect_commit_failure();
+    test.buy(&sale, BuyArgs { amount: dec!(500), credential: holder(1), ..Default::default() })
+        .expect_commit_success();
+    test.buy(&sale, BuyArgs { amount: dec!(500), credential: holder(2), ..Default::default() })
+        .expect_commit_failure();
+
+    test.call_owner_method(&sale, "unblock_jurisdictions", manifest_args!(vec!["US".to_string()]))
+        .expect_commit_success();
+    test.buy(&sale, BuyArgs { amount: dec!(500), credential: holder(2), ..Default::default() })
+        .expect_commit_success();
+
+    // The third credential expires before the sale ends
+    test.buy(&sale, BuyArgs { amount: dec!(500), credential: holder(3), ..Default::default() })
+        .expect_commit_success();
+    test.set_time(150);
+    test.buy(&sale, BuyArgs { amount: dec!(500), credential: holder(3), ..Default::default() })
+        .expect_commit_failure();
+}
+
+#[test]
+fn test_sale_info_reports_the_whole_sale_in_one_call() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(50);
+    let vesting = VestingSchedule { tge_unlock: dec!("0.5"), cliff: 10, duration: 100, step: None };
+    let config = LaunchpadConfig {
+        soft_cap: Some(dec!(1000)),
+        hard_cap: Some(dec!(4000)),
+        vesting: Some(vesting),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+
+    let info: SaleInfo = test.query(&sale, "get_sale_info");
+    assert_eq!(info.phase, SalePhase::Upcoming);
+    assert_eq!(info.current_price, dec!(5));
+    assert_eq!(info.hard_cap, Some(dec!(4000)));
+    assert_eq!(info.tokens_remaining, dec!(1000));
+    assert_eq!(info.participants, None);
+
+    // Without a buyer badge any NFT presented as one identifies nobody
+    test.set_time(150);
+    let nft = test.ledger.create_non_fungible_resource(test.account);
+    test.buy(&sale, BuyArgs {
+        amount: dec!(500),
+        badge: Some(NonFungibleGlobalId::new(nft, NonFungibleLocalId::integer(1))),
+        ..Default::default()
+    }).expect_commit_success();
+    test.buy(&sale, dec!(1000)).expect_commit_success();
+    let info: SaleInfo = test.query(&sale, "get_sale_info");
+    assert_eq!(info.phase, SalePhase::Active);
+    assert_eq!(info.total_raised, dec!(1500));
+    assert_eq!(info.total_committed, dec!(1500));
+    assert_eq!(info.tokens_sold, dec!(300));
+    assert_eq!(info.tokens_remaining, dec!(700));
+    assert_eq!(info.participants, None);
+    assert_eq!(info.purchases, 2);
+    assert_eq!(info.vesting.map(|vesting| vesting.duration), Some(100));
+
+    test.set_time(200);
+    let info: SaleInfo = test.query(&sale, "get_sale_info");
+    assert_eq!(info.phase, SalePhase::Ended);
+    assert_eq!(info.soft_cap, Some(dec!(1000)));
+}
+
+#[test]
+fn test_statistics_survive_withdrawals() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    let badge = test.ledger.create_non_fungible_resource(test.account);
+    let first = NonFungibleGlobalId::new(badge, NonFungibleLocalId::integer(1));
+    let second = NonFungibleGlobalId::new(badge, NonFungibleLocalId::integer(2));
+    let config = LaunchpadConfig {
+        buyer_badge: Some(badge),
+        ..Default::default()
+    };
+    let sale = test.instantiate_launchpad(100, 200, dec!(5), dec!(1000), config);
+
+    test.buy(&sale, BuyArgs {
+        amount: dec!(500),
+        badge: Some(first.clone()),
+        ..Default::default()
+    }).expect_commit_success();
+    test.buy(&sale, BuyArgs {
+        amount: dec!(1000),
+        badge: Some(first),
+        ..Default::default()
+    }).expect_commit_success();
+    test.buy(&sale, BuyArgs {
+        amount: dec!(500),
+        badge: Some(second),
+        ..Default::default()
+    }).expect_commit_success();
+    assert_eq!(test.query::<u64>(&sale, "purchase_count"), 3);
+    assert_eq!(test.query::<Option<u64>>(&sale, "unique_buyers"), Some(2));
+
+    test.set_time(200);
+    test.call_owner_method(&sale, "withdraw_funds", manifest_args!())
+        .expect_commit_success();
+    test.claim_all(&sale).expect_commit_success();
+    assert_eq!(test.query::<Decimal>(&sale, "pay_token_vault_amount"), dec!(0));
+    assert_eq!(test.query::<Decimal>(&sale, "total_raised"), dec!(2000));
+    assert_eq!(test.query::<Decimal>(&sale, "total_tokens_sold"), dec!(400));
+    assert_eq!(test.query::<Decimal>(&sale, "total_claimed"), dec!(400));
+    assert_eq!(test.query::<u64>(&sale, "purchase_count"), 3);
+}
+
+#[test]
+fn test_buy_rounds_to_the_sold_token_divisibility_and_returns_the_dust() {
+    let mut test = TestLaunchpad::new();
+    test.set_time(100);
+    test.sold_token = test.ledger.create_fungible_resource(dec!(1000), DIVISIBILITY_NONE, test.account);
+    let sale = test.instantiate_launchpad(100, 200, dec!(3), dec!(1000), LaunchpadConfig::default());
+    let pay_token = test.pay_token;
+
+    // 10 buys 3 whole tokens for exactly 9 and the remaining 1 comes back
+    let pay_before = test.balance(pay_token);
+    let receipt = test.buy(&sale, dec!(10));
+    let events = test.ledger.extract_events_of_type::<PurchaseEvent>(receipt.expect_commit_success());
+    assert_eq!(events[0].token_amount, dec!(3));
+    assert_eq!(events[0].pay_amount, dec!(9));
+    assert_eq!(pay_before - test.balance(pay_token), dec!(9));
+
+    test.buy(&sale, dec!(2)).expect_commit_failure();
+}